@@ -1,34 +1,53 @@
-use std::rc::Rc;
-use swc_common::{BytePos, LineCol, SourceMap};
-use swc_ecma_ast::Module;
+use std::{io, rc::Rc};
+use swc_common::{comments::Comments, BytePos, LineCol, SourceMap};
+use swc_ecma_ast::{EsVersion, Module};
 use swc_ecma_codegen::{
     self,
     text_writer::{JsWriter, WriteJs},
     Emitter,
 };
 
-pub fn emit_module(cm: Rc<SourceMap>, module: Module) -> (Vec<(BytePos, LineCol)>, Vec<u8>) {
-    let mut buf = vec![];
+/// The subset of `swc_ecma_codegen::Config` that can be configured. swc's
+/// codegen has no quote style or semicolon preference to expose.
+#[derive(Default)]
+pub struct CodegenOptions {
+    pub target: EsVersion,
+    pub ascii_only: bool,
+    pub minify: bool,
+}
+
+/// Writes the code of `module` to `w`, returning the positions to build its
+/// source map from.
+pub fn emit_module<W: io::Write>(
+    cm: Rc<SourceMap>,
+    module: Module,
+    options: &CodegenOptions,
+    comments: Option<&dyn Comments>,
+    w: W,
+) -> io::Result<Vec<(BytePos, LineCol)>> {
     let mut srcmap = vec![];
     {
         let wr = Box::new(JsWriter::new(
             Default::default(),
             "\n",
-            &mut buf,
+            w,
             Some(&mut srcmap),
         )) as Box<dyn WriteJs>;
 
         let mut emitter = Emitter {
             cfg: swc_ecma_codegen::Config {
+                target: options.target,
+                ascii_only: options.ascii_only,
+                minify: options.minify,
                 ..Default::default()
             },
             cm: cm,
-            comments: None,
+            comments,
             wr,
         };
 
-        emitter.emit_module(&module).unwrap();
+        emitter.emit_module(&module)?;
     }
 
-    (srcmap, buf)
+    Ok(srcmap)
 }
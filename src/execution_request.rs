@@ -1,25 +1,77 @@
+mod asset_loader;
+mod builder;
 mod declaration;
+mod get_cjs_module_declarations;
 mod get_inline_source_map;
 mod get_module_declarations;
 mod get_references_from_declaration;
+mod hoist_string_literals;
+mod import_map;
 mod load_module_declaration;
+mod output_format;
+mod resolve_uri;
 mod source_graph;
+mod source_graph_metafile;
+mod source_graph_serde;
 mod source_graph_to_js_execution_code;
+mod tar_file_loader;
 
-use crate::{funee_identifier::FuneeIdentifier, run_js::run_js};
+use crate::{
+    funee_identifier::FuneeIdentifier,
+    host::{op_identifier, Host, RawExtension},
+    load_module::SourceTransforms,
+    run_js::{globals_shim, run_js, SCRIPT_NAME},
+};
 use ast::Expr;
-use deno_core::{error::AnyError, OpDecl};
-use std::collections::HashMap;
-use swc_common::{source_map::RealFileLoader, FileLoader};
+use deno_core::{error::AnyError, serde_json, Extension, OpDecl};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::PathBuf,
+};
+use swc_common::source_map::RealFileLoader;
 use swc_ecma_ast as ast;
 
-use self::source_graph::{LoadParams, SourceGraph};
+use self::source_graph::LoadParams;
+pub use self::{
+    asset_loader::AssetLoader,
+    builder::ExecutionRequestBuilder,
+    declaration::{HostCall, VarInitKind},
+    hoist_string_literals::StringLiteralHoisting,
+    import_map::ImportMap,
+    load_module_declaration::resolve_all,
+    output_format::OutputFormat,
+    resolve_uri::DEFAULT_RESOLVE_EXTENSIONS,
+    source_graph::{EntryMode, LoadLimits, MissingDeclaration, OnUnresolved, SourceGraph},
+    source_graph_to_js_execution_code::{EmitOptions, SourceMapMode},
+    tar_file_loader::TarFileLoader,
+};
+pub use crate::{
+    emit_module::CodegenOptions,
+    load_module::{BinaryFileLoader, JsxRuntime, ModuleLoader, ParseOptions, SourceTransform},
+};
 
 pub struct ExecutionRequest {
     expression: Expr,
+    entry_mode: EntryMode,
     scope: String,
     host_functions: HashMap<FuneeIdentifier, OpDecl>,
-    file_loader: Box<dyn FileLoader + Sync + Send>,
+    host: Option<Box<dyn Host>>,
+    file_loader: Box<dyn BinaryFileLoader + Sync + Send>,
+    emit_options: EmitOptions,
+    metafile: Option<PathBuf>,
+    load_limits: LoadLimits,
+    asset_loaders: HashMap<String, AssetLoader>,
+    source_transforms: SourceTransforms,
+    parse_options: ParseOptions,
+    shims: Vec<String>,
+    on_unresolved: OnUnresolved,
+    import_map: ImportMap,
+    resolve_extensions: Vec<String>,
+    globals: HashMap<String, serde_json::Value>,
+    trace_resolution: bool,
+    allowed_extensions: Option<HashSet<String>>,
+    prune_unused_ops: bool,
 }
 
 impl Default for ExecutionRequest {
@@ -28,36 +80,187 @@ impl Default for ExecutionRequest {
             expression: ast::Expr::Lit(ast::Lit::Null(ast::Null {
                 span: Default::default(),
             })),
+            entry_mode: Default::default(),
             scope: "".to_string(),
             host_functions: HashMap::new(),
+            host: None,
             file_loader: Box::new(RealFileLoader),
+            emit_options: Default::default(),
+            metafile: None,
+            load_limits: Default::default(),
+            asset_loaders: HashMap::new(),
+            source_transforms: HashMap::new(),
+            parse_options: Default::default(),
+            shims: vec![],
+            on_unresolved: Default::default(),
+            import_map: Default::default(),
+            resolve_extensions: DEFAULT_RESOLVE_EXTENSIONS.map(String::from).to_vec(),
+            globals: HashMap::new(),
+            trace_resolution: false,
+            allowed_extensions: None,
+            prune_unused_ops: false,
         }
     }
 }
 
 impl ExecutionRequest {
-    pub fn execute(self) -> Result<(), AnyError> {
+    pub fn builder() -> ExecutionRequestBuilder {
+        Default::default()
+    }
+
+    /// Runs the expression and returns the value it evaluates to, awaited if
+    /// it is a promise.
+    pub fn execute(mut self) -> Result<serde_json::Value, AnyError> {
+        let runner = self.take_runner();
+        let source_graph = self.load(&runner.host_functions, &runner.extensions)?;
+        runner.run(source_graph)
+    }
+
+    /// Loads the graph `execute` would run, such as to serialize it with
+    /// `SourceGraph::to_json`.
+    pub fn load_source_graph(mut self) -> Result<SourceGraph, AnyError> {
+        let runner = self.take_runner();
+        self.load(&runner.host_functions, &runner.extensions)
+    }
+
+    /// Runs a graph loaded earlier, such as by `SourceGraph::from_json`,
+    /// with the host and options of this request. The entry file and the
+    /// options of loading are not used.
+    pub fn execute_source_graph(
+        mut self,
+        source_graph: SourceGraph,
+    ) -> Result<serde_json::Value, AnyError> {
+        self.take_runner().run(source_graph)
+    }
+
+    /// Writes the code `execute` would run to `w` as it is emitted, rather
+    /// than building it as a string first. Returns the source map JSON when
+    /// `SourceMapMode::External` is requested.
+    pub fn bundle_to_writer<W: Write>(mut self, w: &mut W) -> Result<Option<String>, AnyError> {
+        let runner = self.take_runner();
+        let source_graph = self.load(&runner.host_functions, &runner.extensions)?;
+        Ok(source_graph.write_js_execution_code(&runner.emit_options, w)?)
+    }
+
+    /// Takes what running a graph needs out of the request, leaving what
+    /// loading one needs.
+    fn take_runner(&mut self) -> Runner {
+        let mut shims = vec![globals_shim(&self.globals)];
+        shims.extend(std::mem::take(&mut self.shims));
+        Runner {
+            host_functions: std::mem::take(&mut self.host_functions),
+            extensions: match &self.host {
+                Some(host) => host.get_extensions(),
+                None => vec![],
+            },
+            emit_options: std::mem::take(&mut self.emit_options),
+            metafile: self.metafile.take(),
+            prune_unused_ops: self.prune_unused_ops,
+            shims,
+        }
+    }
+
+    /// Loads the graph of the expression, in which both `host_functions` and
+    /// the ops of `extensions` are importable.
+    fn load(
+        self,
+        host_functions: &HashMap<FuneeIdentifier, OpDecl>,
+        extensions: &[RawExtension],
+    ) -> Result<SourceGraph, AnyError> {
+        let host_ops: HashMap<FuneeIdentifier, &OpDecl> = host_functions
+            .iter()
+            .map(|(identifier, op)| (identifier.clone(), op))
+            .chain(
+                extensions
+                    .iter()
+                    .flat_map(|extension| &extension.ops)
+                    .map(|op| (op_identifier(op), op)),
+            )
+            .collect();
+
         let source_graph = SourceGraph::load(LoadParams {
             scope: self.scope,
             expression: self.expression,
-            host_functions: self.host_functions.keys().cloned().collect(),
+            entry_mode: self.entry_mode,
+            host_functions: host_ops.keys().cloned().collect(),
+            async_host_functions: host_ops
+                .iter()
+                .filter(|(_, op)| op.is_async)
+                .map(|(identifier, _)| identifier.clone())
+                .collect(),
             file_loader: self.file_loader,
-        });
+            limits: self.load_limits,
+            asset_loaders: self.asset_loaders,
+            source_transforms: self.source_transforms,
+            parse_options: self.parse_options,
+            on_unresolved: self.on_unresolved,
+            import_map: self.import_map,
+            resolve_extensions: self.resolve_extensions,
+            runtime_globals: self.globals.keys().cloned().collect(),
+            trace_resolution: self.trace_resolution,
+            allowed_extensions: self.allowed_extensions,
+        })?;
+
+        for warning in &source_graph.warnings {
+            eprintln!("warning: {}", warning);
+        }
+        for resolution in &source_graph.resolution_trace {
+            eprintln!("resolve: {}", resolution);
+        }
+        Ok(source_graph)
+    }
+}
+
+/// What running a loaded graph needs from its request.
+struct Runner {
+    host_functions: HashMap<FuneeIdentifier, OpDecl>,
+    extensions: Vec<RawExtension>,
+    emit_options: EmitOptions,
+    metafile: Option<PathBuf>,
+    prune_unused_ops: bool,
+    shims: Vec<String>,
+}
+
+impl Runner {
+    fn run(self, source_graph: SourceGraph) -> Result<serde_json::Value, AnyError> {
+        let execution_code = source_graph.to_js_execution_code(&self.emit_options);
 
-        let execution_code = source_graph.into_js_execution_code();
+        if let Some(metafile) = &self.metafile {
+            std::fs::write(
+                metafile,
+                source_graph
+                    .metafile(SCRIPT_NAME, &execution_code)
+                    .to_string(),
+            )?;
+        }
 
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_all()
             .build()?;
 
-        if let Err(error) = runtime.block_on(run_js(
-            &execution_code,
-            self.host_functions.into_values().collect(),
-        )) {
-            eprintln!("error: {}", error);
+        // Code may call ops it doesn't import, such as through
+        // `Deno.core.opSync`, so only an opted in request leaves out the ops
+        // of host functions the bundle doesn't reference.
+        let used_host_functions = source_graph.used_host_functions();
+        let prune_unused_ops = self.prune_unused_ops;
+        let is_registered = |identifier: &FuneeIdentifier| {
+            !prune_unused_ops || used_host_functions.contains(identifier)
+        };
+        let ops = self
+            .host_functions
+            .into_iter()
+            .filter(|(identifier, _)| is_registered(identifier))
+            .map(|(_, op)| op)
+            .collect();
+        let mut extensions = self.extensions;
+        for extension in &mut extensions {
+            extension.ops.retain(|op| is_registered(&op_identifier(op)));
         }
+        let extensions = std::iter::once(Extension::builder().ops(ops).build())
+            .chain(extensions.into_iter().map(RawExtension::into_extension))
+            .collect();
 
-        Ok(())
+        runtime.block_on(run_js(&execution_code, extensions, &self.shims))
     }
 }
 
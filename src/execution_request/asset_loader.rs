@@ -0,0 +1,91 @@
+use crate::load_module::ModuleLoader;
+use deno_core::{
+    error::{generic_error, AnyError},
+    serde_json::{self, Value},
+};
+use std::{path::Path, rc::Rc};
+use swc_common::SourceMap;
+use swc_ecma_ast::{
+    ArrayLit, ComputedPropName, Expr, ExprOrSpread, KeyValueProp, Lit, Null, ObjectLit, Prop,
+    PropName, PropOrSpread,
+};
+
+pub enum AssetLoader {
+    Text,
+    Base64,
+}
+
+pub const CODE_EXTENSIONS: [&str; 8] = ["ts", "tsx", "mts", "cts", "js", "jsx", "mjs", "cjs"];
+
+pub fn load_asset(
+    module_loader: &ModuleLoader,
+    path: &Path,
+    loader: &AssetLoader,
+) -> Result<Expr, AnyError> {
+    let value = match loader {
+        AssetLoader::Text => module_loader.cm.load_file(path)?.src.to_string(),
+        // Read as bytes, as binary files such as images aren't valid UTF-8.
+        AssetLoader::Base64 => base64::encode(module_loader.read_bytes(path)?),
+    };
+    Ok(Expr::Lit(Lit::Str(value.into())))
+}
+
+/// The contents of a JSON file as the literal expression they denote.
+pub fn load_json(cm: &Rc<SourceMap>, path: &Path) -> Result<Expr, AnyError> {
+    let file = cm.load_file(path)?;
+    let value: Value = serde_json::from_str(&file.src).map_err(|error| {
+        generic_error(format!(
+            "Could not parse {} as JSON: {}",
+            path.display(),
+            error
+        ))
+    })?;
+    Ok(json_to_expr(value))
+}
+
+fn json_to_expr(value: Value) -> Expr {
+    match value {
+        Value::Null => Expr::Lit(Lit::Null(Null {
+            span: Default::default(),
+        })),
+        Value::Bool(value) => Expr::Lit(Lit::Bool(value.into())),
+        Value::Number(value) => Expr::Lit(Lit::Num(value.as_f64().unwrap_or(f64::NAN).into())),
+        Value::String(value) => Expr::Lit(Lit::Str(value.into())),
+        Value::Array(values) => Expr::Array(ArrayLit {
+            span: Default::default(),
+            elems: values
+                .into_iter()
+                .map(|value| {
+                    Some(ExprOrSpread {
+                        spread: None,
+                        expr: Box::new(json_to_expr(value)),
+                    })
+                })
+                .collect(),
+        }),
+        Value::Object(entries) => Expr::Object(ObjectLit {
+            span: Default::default(),
+            props: entries
+                .into_iter()
+                .map(|(key, value)| {
+                    PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
+                        key: json_key(key),
+                        value: Box::new(json_to_expr(value)),
+                    })))
+                })
+                .collect(),
+        }),
+    }
+}
+
+/// A `__proto__` key written as is would set the object's prototype instead
+/// of defining a property, as JSON.parse does, so it is computed.
+fn json_key(key: String) -> PropName {
+    if key == "__proto__" {
+        return PropName::Computed(ComputedPropName {
+            span: Default::default(),
+            expr: Box::new(Expr::Lit(Lit::Str(key.into()))),
+        });
+    }
+    PropName::Str(key.into())
+}
@@ -0,0 +1,199 @@
+use super::{
+    asset_loader::AssetLoader,
+    import_map::ImportMap,
+    source_graph::{EntryMode, LoadLimits, OnUnresolved},
+    source_graph_to_js_execution_code::EmitOptions,
+    ExecutionRequest,
+};
+use crate::{
+    host::Host,
+    load_module::{BinaryFileLoader, ParseOptions, SourceTransforms},
+};
+use deno_core::{
+    error::{generic_error, AnyError},
+    serde_json,
+};
+use std::{collections::HashMap, path::PathBuf};
+use swc_common::{FileName, SourceMap, Span, DUMMY_SP};
+use swc_ecma_ast::{CallExpr, Callee, EsVersion, Expr, ExprOrSpread, Ident};
+use swc_ecma_parser::{parse_file_as_expr, Syntax};
+use swc_ecma_visit::{noop_visit_mut_type, VisitMut, VisitMutWith};
+
+/// Builds a request that calls an export of an entry file.
+#[derive(Default)]
+pub struct ExecutionRequestBuilder {
+    entry_file: Option<String>,
+    export: Option<String>,
+    args: Vec<String>,
+    /// Holds every other option until `build` fills in the expression.
+    request: ExecutionRequest,
+}
+
+impl ExecutionRequestBuilder {
+    pub fn entry_file(mut self, path: impl Into<String>) -> Self {
+        self.entry_file = Some(path.into());
+        self
+    }
+
+    /// Set to `EntryMode::Script` to run the entry file's statements instead of
+    /// calling an export.
+    pub fn entry_mode(mut self, entry_mode: EntryMode) -> Self {
+        self.request.entry_mode = entry_mode;
+        self
+    }
+
+    /// The export to call, `default` unless set.
+    pub fn export(mut self, name: impl Into<String>) -> Self {
+        self.export = Some(name.into());
+        self
+    }
+
+    /// The expressions passed to the export, written as source such as
+    /// `"42"` or `"{ verbose: true }"`.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn host(mut self, host: Box<dyn Host>) -> Self {
+        self.request.host = Some(host);
+        self
+    }
+
+    pub fn file_loader(mut self, file_loader: Box<dyn BinaryFileLoader + Sync + Send>) -> Self {
+        self.request.file_loader = file_loader;
+        self
+    }
+
+    pub fn emit_options(mut self, emit_options: EmitOptions) -> Self {
+        self.request.emit_options = emit_options;
+        self
+    }
+
+    pub fn load_limits(mut self, load_limits: LoadLimits) -> Self {
+        self.request.load_limits = load_limits;
+        self
+    }
+
+    /// The loaders of imported assets, by file extension.
+    pub fn asset_loaders(mut self, asset_loaders: HashMap<String, AssetLoader>) -> Self {
+        self.request.asset_loaders = asset_loaders;
+        self
+    }
+
+    /// The transforms applied to sources before parsing, by file extension.
+    pub fn source_transforms(mut self, source_transforms: SourceTransforms) -> Self {
+        self.request.source_transforms = source_transforms;
+        self
+    }
+
+    pub fn parse_options(mut self, parse_options: ParseOptions) -> Self {
+        self.request.parse_options = parse_options;
+        self
+    }
+
+    pub fn on_unresolved(mut self, on_unresolved: OnUnresolved) -> Self {
+        self.request.on_unresolved = on_unresolved;
+        self
+    }
+
+    pub fn import_map(mut self, import_map: ImportMap) -> Self {
+        self.request.import_map = import_map;
+        self
+    }
+
+    /// Values defined on `globalThis` before the code runs.
+    pub fn globals(mut self, globals: HashMap<String, serde_json::Value>) -> Self {
+        self.request.globals = globals;
+        self
+    }
+
+    /// Only files with one of these extensions may be loaded.
+    pub fn allowed_extensions<I, S>(mut self, extensions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.request.allowed_extensions = Some(extensions.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Where to write the metafile describing the loaded graph.
+    pub fn metafile(mut self, path: impl Into<PathBuf>) -> Self {
+        self.request.metafile = Some(path.into());
+        self
+    }
+
+    /// Registers only the host functions the graph uses.
+    pub fn prune_unused_ops(mut self, prune_unused_ops: bool) -> Self {
+        self.request.prune_unused_ops = prune_unused_ops;
+        self
+    }
+
+    pub fn build(self) -> Result<ExecutionRequest, AnyError> {
+        let entry_file = self
+            .entry_file
+            .ok_or_else(|| generic_error("An execution request needs an entry file"))?;
+        let export = self.export.unwrap_or_else(|| "default".to_string());
+        let args = self
+            .args
+            .iter()
+            .map(|arg| {
+                Ok(ExprOrSpread {
+                    spread: None,
+                    expr: parse_arg(arg)?,
+                })
+            })
+            .collect::<Result<_, AnyError>>()?;
+        Ok(ExecutionRequest {
+            expression: Expr::Call(CallExpr {
+                span: Default::default(),
+                callee: Callee::Expr(Box::new(Expr::Ident(Ident::new(
+                    export.into(),
+                    Default::default(),
+                )))),
+                args,
+                type_args: None,
+            }),
+            scope: entry_file,
+            ..self.request
+        })
+    }
+}
+
+/// Arguments are parsed in a source map of their own, so their spans would
+/// point into unrelated modules of the graph's source map.
+struct ResetSpans;
+
+impl VisitMut for ResetSpans {
+    noop_visit_mut_type!();
+
+    fn visit_mut_span(&mut self, span: &mut Span) {
+        *span = DUMMY_SP;
+    }
+}
+
+fn parse_arg(source: &str) -> Result<Box<Expr>, AnyError> {
+    let cm = SourceMap::default();
+    let fm = cm.new_source_file(FileName::Anon, source.to_string());
+    let mut expr = parse_file_as_expr(
+        &fm,
+        Syntax::Typescript(Default::default()),
+        EsVersion::latest(),
+        None,
+        &mut vec![],
+    )
+    .map_err(|error| {
+        generic_error(format!(
+            "Could not parse argument {}: {}",
+            source,
+            error.kind().msg()
+        ))
+    })?;
+    expr.visit_mut_with(&mut ResetSpans);
+    Ok(expr)
+}
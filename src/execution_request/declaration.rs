@@ -1,25 +1,81 @@
 use crate::funee_identifier::FuneeIdentifier;
+use serde::{Deserialize, Serialize};
+use swc_common::Spanned;
 use swc_ecma_ast::{
-    BlockStmt, CallExpr, Callee, ComputedPropName, Decl, Expr, ExprOrSpread, ExprStmt, FnDecl,
-    FnExpr, Ident, Lit, MemberExpr, MemberProp, ModuleItem, Param, Pat, RestPat, ReturnStmt, Stmt,
+    AwaitExpr, BlockStmt, CallExpr, Callee, ClassDecl, ComputedPropName, Decl, Expr, ExprOrSpread,
+    ExprStmt, FnDecl, FnExpr, Ident, Lit, MemberExpr, MemberProp, ModuleItem, Param, Pat, RestPat,
+    ReturnStmt, Stmt, VarDecl, VarDeclKind, VarDeclarator,
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Declaration {
     Expr(Expr),
     FnExpr(FnExpr),
     FnDecl(FnDecl),
+    Class(ClassDecl),
+    /// A value bound to a name, along with the kind of the variable
+    /// declaration it came from. Values funee synthesizes, such as assets and
+    /// namespace objects, have no kind.
+    VarInit(Expr, Option<VarDeclKind>),
     FuneeIdentifier(FuneeIdentifier),
-    HostFn(String),
+    /// Another name declared in the same module, such as the local `f` of
+    /// `export { f as g }`.
+    LocalAlias(String),
+    HostFn {
+        name: String,
+        is_async: bool,
+    },
+}
+
+/// The kind of variable declaration a `Declaration::VarInit` funee synthesized
+/// is emitted as. Bindings from the source keep the kind they were declared
+/// with, so that `let` and `var` stay assignable.
+#[derive(Default, Clone, Copy)]
+pub enum VarInitKind {
+    #[default]
+    Const,
+    Let,
+    Var,
+}
+
+/// How the functions emitted for `Declaration::HostFn` call their op.
+#[derive(Default)]
+pub enum HostCall {
+    /// `Deno.core.opSync("op_<name>", args[0])`, or `Deno.core.opAsync` for
+    /// async ops.
+    #[default]
+    DenoCore,
+    /// `<callee>("<name>", ...args)`, for runtimes that expose host functions
+    /// through a global such as `globalThis.__host.call`.
+    Global { callee: String },
+}
+
+impl From<VarInitKind> for VarDeclKind {
+    fn from(kind: VarInitKind) -> Self {
+        match kind {
+            VarInitKind::Const => VarDeclKind::Const,
+            VarInitKind::Let => VarDeclKind::Let,
+            VarInitKind::Var => VarDeclKind::Var,
+        }
+    }
 }
 
 impl Declaration {
-    pub fn into_module_item(self, name: String) -> ModuleItem {
+    pub fn into_module_item(
+        self,
+        name: String,
+        var_init_kind: VarInitKind,
+        host_call: &HostCall,
+    ) -> ModuleItem {
         ModuleItem::Stmt(match self {
             Declaration::FnDecl(mut fn_decl) => {
                 fn_decl.ident.sym = name.into();
                 Stmt::Decl(Decl::Fn(fn_decl))
             }
+            Declaration::Class(mut class_decl) => {
+                class_decl.ident.sym = name.into();
+                Stmt::Decl(Decl::Class(class_decl))
+            }
             Declaration::FnExpr(fn_expr) => {
                 let fn_decl = FnDecl {
                     ident: Ident::new(name.into(), Default::default()),
@@ -28,12 +84,26 @@ impl Declaration {
                 };
                 Stmt::Decl(Decl::Fn(fn_decl))
             }
+            Declaration::VarInit(init, kind) => Stmt::Decl(Decl::Var(VarDecl {
+                span: init.span(),
+                kind: kind.unwrap_or_else(|| var_init_kind.into()),
+                declare: false,
+                decls: vec![VarDeclarator {
+                    span: Default::default(),
+                    name: Pat::Ident(Ident::new(name.into(), Default::default()).into()),
+                    init: Some(Box::new(init)),
+                    definite: false,
+                }],
+            })),
             Declaration::Expr(fn_expr) => Stmt::Expr(ExprStmt {
                 span: Default::default(),
                 expr: Box::new(fn_expr),
             }),
-            Declaration::FuneeIdentifier(_) => unreachable!(),
-            Declaration::HostFn(op_name) => Stmt::Decl(Decl::Fn(FnDecl {
+            Declaration::FuneeIdentifier(_) | Declaration::LocalAlias(_) => unreachable!(),
+            Declaration::HostFn {
+                name: op_name,
+                is_async,
+            } => Stmt::Decl(Decl::Fn(FnDecl {
                 ident: Ident::new(name.into(), Default::default()),
                 declare: Default::default(),
                 function: swc_ecma_ast::Function {
@@ -55,54 +125,11 @@ impl Declaration {
                         span: Default::default(),
                         stmts: vec![Stmt::Return(ReturnStmt {
                             span: Default::default(),
-                            arg: Some(Box::new(Expr::Call(CallExpr {
-                                span: Default::default(),
-                                type_args: None,
-                                args: vec![
-                                    ExprOrSpread {
-                                        expr: Box::new(Expr::Lit(Lit::Str(
-                                            ("op_".to_string() + &op_name).into(),
-                                        ))),
-                                        spread: None,
-                                    },
-                                    ExprOrSpread {
-                                        expr: Box::new(Expr::Member(MemberExpr {
-                                            span: Default::default(),
-                                            obj: Box::new(Expr::Ident(Ident::new(
-                                                "args".into(),
-                                                Default::default(),
-                                            ))),
-                                            prop: MemberProp::Computed(ComputedPropName {
-                                                span: Default::default(),
-                                                expr: Box::new(Expr::Lit(Lit::Num(0.into()))),
-                                            }),
-                                        })),
-                                        spread: None,
-                                    },
-                                ],
-                                callee: Callee::Expr(Box::new(Expr::Member(MemberExpr {
-                                    span: Default::default(),
-                                    prop: swc_ecma_ast::MemberProp::Ident(Ident::new(
-                                        "opSync".into(),
-                                        Default::default(),
-                                    )),
-                                    obj: Box::new(Expr::Member(MemberExpr {
-                                        span: Default::default(),
-                                        prop: swc_ecma_ast::MemberProp::Ident(Ident::new(
-                                            "core".into(),
-                                            Default::default(),
-                                        )),
-                                        obj: Box::new(Expr::Ident(Ident::new(
-                                            "Deno".into(),
-                                            Default::default(),
-                                        ))),
-                                    })),
-                                }))),
-                            }))),
+                            arg: Some(Box::new(host_fn_call(op_name, is_async, host_call))),
                         })],
                     }),
                     is_generator: false,
-                    is_async: false,
+                    is_async,
                     type_params: None,
                     return_type: None,
                 },
@@ -110,3 +137,77 @@ impl Declaration {
         })
     }
 }
+
+/// The call a host function makes to its op, awaited when the op is async.
+fn host_fn_call(op_name: String, is_async: bool, host_call: &HostCall) -> Expr {
+    let args = Expr::Ident(Ident::new("args".into(), Default::default()));
+    let (callee, args) = match host_call {
+        HostCall::DenoCore => (
+            member_path(if is_async {
+                "Deno.core.opAsync"
+            } else {
+                "Deno.core.opSync"
+            }),
+            vec![
+                ExprOrSpread {
+                    expr: Box::new(Expr::Lit(Lit::Str(("op_".to_string() + &op_name).into()))),
+                    spread: None,
+                },
+                ExprOrSpread {
+                    expr: Box::new(Expr::Member(MemberExpr {
+                        span: Default::default(),
+                        obj: Box::new(args),
+                        prop: MemberProp::Computed(ComputedPropName {
+                            span: Default::default(),
+                            expr: Box::new(Expr::Lit(Lit::Num(0.into()))),
+                        }),
+                    })),
+                    spread: None,
+                },
+            ],
+        ),
+        HostCall::Global { callee } => (
+            member_path(callee),
+            vec![
+                ExprOrSpread {
+                    expr: Box::new(Expr::Lit(Lit::Str(op_name.into()))),
+                    spread: None,
+                },
+                ExprOrSpread {
+                    expr: Box::new(args),
+                    spread: Some(Default::default()),
+                },
+            ],
+        ),
+    };
+    let call = Expr::Call(CallExpr {
+        span: Default::default(),
+        type_args: None,
+        args,
+        callee: Callee::Expr(Box::new(callee)),
+    });
+    if is_async {
+        Expr::Await(AwaitExpr {
+            span: Default::default(),
+            arg: Box::new(call),
+        })
+    } else {
+        call
+    }
+}
+
+/// A dotted path such as `Deno.core.opSync` as a member expression.
+fn member_path(path: &str) -> Expr {
+    let mut segments = path.split('.');
+    let root = Expr::Ident(Ident::new(
+        segments.next().unwrap_or_default().into(),
+        Default::default(),
+    ));
+    segments.fold(root, |obj, prop| {
+        Expr::Member(MemberExpr {
+            span: Default::default(),
+            obj: Box::new(obj),
+            prop: MemberProp::Ident(Ident::new(prop.into(), Default::default())),
+        })
+    })
+}
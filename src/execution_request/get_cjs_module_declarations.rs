@@ -0,0 +1,244 @@
+use super::{declaration::Declaration, get_module_declarations::ModuleDeclaration};
+use crate::funee_identifier::FuneeIdentifier;
+use std::collections::HashMap;
+use swc_ecma_ast::{
+    AssignOp, Callee, Decl, Expr, ExprStmt, FnExpr, Lit, MemberExpr, MemberProp, Module,
+    ModuleItem, ObjectPatProp, Pat, PatOrExpr, Prop, PropName, PropOrSpread, Stmt, VarDeclKind,
+    VarDeclarator,
+};
+
+pub fn is_cjs_module(uri: &str, module: &Module) -> bool {
+    if uri.ends_with(".cjs") {
+        return true;
+    }
+    let has_module_decls = module
+        .body
+        .iter()
+        .any(|module_item| matches!(module_item, ModuleItem::ModuleDecl(_)));
+    let has_cjs_exports = module.body.iter().any(|module_item| match module_item {
+        ModuleItem::Stmt(Stmt::Expr(ExprStmt { expr, .. })) => get_cjs_export(expr).is_some(),
+        _ => false,
+    });
+    !has_module_decls && has_cjs_exports
+}
+
+pub fn get_cjs_module_declarations(module: &Module) -> HashMap<String, ModuleDeclaration> {
+    let mut declarations = HashMap::new();
+    let mut exports = vec![];
+    for module_item in &module.body {
+        match module_item {
+            ModuleItem::Stmt(Stmt::Decl(Decl::Fn(fn_decl))) => {
+                declarations.insert(
+                    fn_decl.ident.sym.to_string(),
+                    ModuleDeclaration {
+                        exported: false,
+                        declaration: Declaration::FnDecl(fn_decl.clone()),
+                    },
+                );
+            }
+            ModuleItem::Stmt(Stmt::Decl(Decl::Class(class_decl))) => {
+                declarations.insert(
+                    class_decl.ident.sym.to_string(),
+                    ModuleDeclaration {
+                        exported: false,
+                        declaration: Declaration::Class(class_decl.clone()),
+                    },
+                );
+            }
+            ModuleItem::Stmt(Stmt::Decl(Decl::Var(var_decl))) => {
+                for declarator in &var_decl.decls {
+                    declarations.extend(get_declarator_declarations(declarator, var_decl.kind));
+                }
+            }
+            ModuleItem::Stmt(Stmt::Expr(ExprStmt { expr, .. })) => match get_cjs_export(expr) {
+                Some(CjsExport::All(value)) => {
+                    // Replacing `module.exports` drops what was assigned to
+                    // the previous exports object.
+                    exports.clear();
+                    if let Expr::Object(object) = value {
+                        exports.extend(object.props.iter().filter_map(|prop| match prop {
+                            PropOrSpread::Prop(prop) => get_prop_export(prop),
+                            PropOrSpread::Spread(_) => None,
+                        }));
+                    }
+                    exports.push(("default".to_string(), value.clone()));
+                }
+                Some(CjsExport::Named(name, value)) => exports.push((name, value.clone())),
+                None => {}
+            },
+            _ => {}
+        }
+    }
+
+    for (name, value) in exports {
+        let declaration = match value {
+            Expr::Ident(ident) => {
+                if ident.sym.as_ref() == name {
+                    if let Some(local) = declarations.get_mut(&name) {
+                        local.exported = true;
+                        continue;
+                    }
+                }
+                Declaration::LocalAlias(ident.sym.to_string())
+            }
+            value => get_declaration_from_expr(value, None),
+        };
+        declarations.insert(
+            name,
+            ModuleDeclaration {
+                exported: true,
+                declaration,
+            },
+        );
+    }
+
+    declarations
+}
+
+enum CjsExport<'a> {
+    All(&'a Expr),
+    Named(String, &'a Expr),
+}
+
+fn get_cjs_export(expr: &Expr) -> Option<CjsExport<'_>> {
+    let assign = match expr {
+        Expr::Assign(assign) if assign.op == AssignOp::Assign => assign,
+        _ => return None,
+    };
+    let target = match &assign.left {
+        PatOrExpr::Expr(expr) => &**expr,
+        PatOrExpr::Pat(pat) => match &**pat {
+            Pat::Expr(expr) => &**expr,
+            _ => return None,
+        },
+    };
+    if is_module_exports(target) {
+        return Some(CjsExport::All(&assign.right));
+    }
+    match target {
+        Expr::Member(MemberExpr {
+            obj,
+            prop: MemberProp::Ident(prop),
+            ..
+        }) if is_ident(obj, "exports") || is_module_exports(obj) => {
+            Some(CjsExport::Named(prop.sym.to_string(), &assign.right))
+        }
+        _ => None,
+    }
+}
+
+fn is_ident(expr: &Expr, name: &str) -> bool {
+    matches!(expr, Expr::Ident(ident) if ident.sym.as_ref() == name)
+}
+
+fn is_module_exports(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Member(MemberExpr {
+            obj,
+            prop: MemberProp::Ident(prop),
+            ..
+        }) if is_ident(obj, "module") && prop.sym.as_ref() == "exports"
+    )
+}
+
+fn get_require_specifier(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Call(call) => match (&call.callee, call.args.as_slice()) {
+            (Callee::Expr(callee), [arg]) if is_ident(callee, "require") => match &*arg.expr {
+                Expr::Lit(Lit::Str(specifier)) => Some(specifier.value.to_string()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn get_prop_name(key: &PropName) -> Option<String> {
+    match key {
+        PropName::Ident(ident) => Some(ident.sym.to_string()),
+        PropName::Str(s) => Some(s.value.to_string()),
+        _ => None,
+    }
+}
+
+fn get_prop_export(prop: &Prop) -> Option<(String, Expr)> {
+    match prop {
+        Prop::Shorthand(ident) => Some((ident.sym.to_string(), Expr::Ident(ident.clone()))),
+        Prop::KeyValue(key_value) => {
+            get_prop_name(&key_value.key).map(|name| (name, (*key_value.value).clone()))
+        }
+        Prop::Method(method) => get_prop_name(&method.key).map(|name| {
+            (
+                name,
+                Expr::Fn(FnExpr {
+                    ident: None,
+                    function: method.function.clone(),
+                }),
+            )
+        }),
+        _ => None,
+    }
+}
+
+fn get_declaration_from_expr(expr: Expr, kind: Option<VarDeclKind>) -> Declaration {
+    match expr {
+        Expr::Fn(fn_expr) => Declaration::FnExpr(fn_expr),
+        expr => Declaration::VarInit(expr, kind),
+    }
+}
+
+fn get_require_declaration(specifier: &str, name: &str) -> ModuleDeclaration {
+    ModuleDeclaration {
+        exported: false,
+        declaration: Declaration::FuneeIdentifier(FuneeIdentifier {
+            name: name.to_string(),
+            uri: specifier.to_string(),
+        }),
+    }
+}
+
+fn get_declarator_declarations(
+    declarator: &VarDeclarator,
+    kind: VarDeclKind,
+) -> Vec<(String, ModuleDeclaration)> {
+    let init = match &declarator.init {
+        Some(init) => init,
+        None => return vec![],
+    };
+    match (&declarator.name, get_require_specifier(init)) {
+        (Pat::Ident(binding), Some(specifier)) => vec![(
+            binding.id.sym.to_string(),
+            get_require_declaration(&specifier, "default"),
+        )],
+        (Pat::Object(object), Some(specifier)) => object
+            .props
+            .iter()
+            .filter_map(|prop| match prop {
+                ObjectPatProp::KeyValue(key_value) => {
+                    match (get_prop_name(&key_value.key), &*key_value.value) {
+                        (Some(imported), Pat::Ident(local)) => Some((
+                            local.id.sym.to_string(),
+                            get_require_declaration(&specifier, &imported),
+                        )),
+                        _ => None,
+                    }
+                }
+                ObjectPatProp::Assign(assign) => Some((
+                    assign.key.sym.to_string(),
+                    get_require_declaration(&specifier, &assign.key.sym),
+                )),
+                ObjectPatProp::Rest(_) => None,
+            })
+            .collect(),
+        (Pat::Ident(binding), None) => vec![(
+            binding.id.sym.to_string(),
+            ModuleDeclaration {
+                exported: false,
+                declaration: get_declaration_from_expr((**init).clone(), Some(kind)),
+            },
+        )],
+        _ => vec![],
+    }
+}
@@ -1,13 +1,31 @@
+use std::path::PathBuf;
 use std::rc::Rc;
 use swc_common::BytePos;
+use swc_common::FileName;
 use swc_common::LineCol;
 use swc_common::SourceMap;
 
-pub fn get_inline_source_map(cm: &Rc<SourceMap>, srcmap: &mut Vec<(BytePos, LineCol)>) -> String {
-    let srcmap = cm.build_source_map(srcmap);
+pub fn get_source_map(cm: &Rc<SourceMap>, srcmap: &mut Vec<(BytePos, LineCol)>) -> Vec<u8> {
+    let mut srcmap = cm.build_source_map(srcmap);
+
+    // swc only embeds the contents of non-file sources, so the loaded files
+    // are added here to make the map self-contained.
+    for index in 0..srcmap.get_source_count() {
+        let source_file = srcmap
+            .get_source(index)
+            .and_then(|source| cm.get_source_file(&FileName::Real(PathBuf::from(source))));
+        if let Some(source_file) = source_file {
+            srcmap.set_source_contents(index, Some(source_file.src.as_str()));
+        }
+    }
 
     let mut output: Vec<u8> = vec![];
     srcmap.to_writer(&mut output).unwrap();
+    output
+}
+
+pub fn get_inline_source_map(cm: &Rc<SourceMap>, srcmap: &mut Vec<(BytePos, LineCol)>) -> String {
+    let output = get_source_map(cm, srcmap);
 
     let mut result = "\n//# sourceMappingURL=data:application/json;base64,".to_string();
     base64::encode_config_buf(&output, base64::STANDARD, &mut result);
@@ -1,20 +1,207 @@
 use super::declaration::Declaration;
 use crate::funee_identifier::FuneeIdentifier;
-use std::{collections::HashMap, path::Path};
+use deno_core::error::{generic_error, AnyError};
+use std::collections::{HashMap, HashSet};
+use swc_common::DUMMY_SP;
 use swc_ecma_ast::{
-    Decl, DefaultDecl, ExportSpecifier, ImportSpecifier, Module, ModuleDecl, ModuleExportName,
-    ModuleItem, Stmt,
+    ArrowExpr, AssignExpr, Class, ClassDecl, Decl, DefaultDecl, ExportDecl, ExportSpecifier, Expr,
+    Function, Id, Ident, ImportSpecifier, Module, ModuleDecl, ModuleExportName, ModuleItem,
+    NamedExport, Pat, PatOrExpr, Stmt, UpdateExpr, VarDecl, VarDeclKind,
 };
+use swc_ecma_utils::find_pat_ids;
+use swc_ecma_visit::{noop_visit_type, Visit, VisitWith};
 
-pub fn get_module_declarations(module: Module) -> HashMap<String, ModuleDeclaration> {
-    HashMap::from_iter(
-        module
-            .body
-            .into_iter()
-            .flat_map(|x| get_module_declarations_from_module_item("".to_string(), x)),
-    )
+/// The name a module's namespace object is declared under.
+pub const NAMESPACE: &str = "*";
+
+/// The declarations of a module's top-level bindings.
+pub struct ModuleDeclarations {
+    pub declarations: HashMap<String, ModuleDeclaration>,
+    /// Bindings whose value can't be told from their declaration, with the
+    /// reason why. Only referencing one of them is an error.
+    pub unsupported: HashMap<String, &'static str>,
+}
+
+pub fn get_module_declarations(module: Module) -> Result<ModuleDeclarations, AnyError> {
+    let assigned = get_assigned_bindings(&module);
+    let mut declarations = HashMap::new();
+    let mut unsupported = HashMap::new();
+    let mut local_bindings = HashSet::new();
+    let mut var_bindings = HashSet::new();
+    let mut local_exports = vec![];
+    for module_item in module.body {
+        let var_decl = match &module_item {
+            ModuleItem::Stmt(Stmt::Decl(Decl::Var(var_decl)))
+            | ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ExportDecl {
+                decl: Decl::Var(var_decl),
+                ..
+            })) => Some(var_decl),
+            _ => None,
+        };
+        for declarator in var_decl.iter().flat_map(|var_decl| &var_decl.decls) {
+            if !matches!(declarator.name, Pat::Ident(_)) {
+                for (name, _) in find_pat_ids::<_, Id>(&declarator.name) {
+                    unsupported.insert(
+                        name.to_string(),
+                        "destructuring is not supported in top-level bindings",
+                    );
+                }
+            }
+        }
+        let var_decl = var_decl.filter(|var_decl| var_decl.kind == VarDeclKind::Var);
+        let uninitialized: HashSet<String> = var_decl
+            .iter()
+            .flat_map(|var_decl| &var_decl.decls)
+            .filter_map(|declarator| match (&declarator.name, &declarator.init) {
+                (Pat::Ident(binding), None) => Some(binding.id.sym.to_string()),
+                _ => None,
+            })
+            .collect();
+        let declares_vars = var_decl.is_some();
+        let declares_local_bindings = matches!(
+            module_item,
+            ModuleItem::ModuleDecl(ModuleDecl::Import(_))
+                | ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(_))
+                | ModuleItem::Stmt(Stmt::Decl(_))
+        );
+        let exports_local_bindings = matches!(
+            module_item,
+            ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(NamedExport { src: None, .. }))
+        );
+        for (name, mut declaration) in get_module_declarations_from_module_item(module_item) {
+            if declares_local_bindings && !local_bindings.insert(name.clone()) {
+                // `var` may redeclare a `var`, which keeps its value unless
+                // given a new initializer.
+                if !(declares_vars && var_bindings.contains(&name)) {
+                    return Err(generic_error(format!(
+                        "Identifier '{}' has already been declared",
+                        name
+                    )));
+                }
+                if uninitialized.contains(&name) {
+                    continue;
+                }
+                declaration.exported |= declarations
+                    .get(&name)
+                    .map_or(false, |previous: &ModuleDeclaration| previous.exported);
+            }
+            if declares_vars {
+                var_bindings.insert(name.clone());
+            }
+            if exports_local_bindings {
+                local_exports.push((name, declaration));
+            } else {
+                declarations.insert(name, declaration);
+            }
+        }
+    }
+
+    // `export { f }` exports the local `f` itself rather than an alias of it,
+    // so that every name `f` is exported under resolves to the same node.
+    for (name, declaration) in local_exports {
+        if let Declaration::LocalAlias(local) = &declaration.declaration {
+            if *local == name {
+                if let Some(local) = declarations.get_mut(&name) {
+                    local.exported = true;
+                    continue;
+                }
+            }
+        }
+        declarations.insert(name, declaration);
+    }
+
+    // Such as the `var` TypeScript lowers an enum to, which the statement
+    // after it fills in.
+    for name in assigned {
+        if let Some(ModuleDeclaration {
+            declaration: Declaration::VarInit(_, Some(_)),
+            ..
+        }) = declarations.get(&name)
+        {
+            declarations.remove(&name);
+            unsupported.insert(name, "it is assigned by a top-level statement");
+        }
+    }
+    Ok(ModuleDeclarations {
+        declarations,
+        unsupported,
+    })
+}
+
+/// The names top-level statements assign to, outside of the functions they
+/// define.
+fn get_assigned_bindings(module: &Module) -> HashSet<String> {
+    let mut finder = AssignedBindings::default();
+    for module_item in &module.body {
+        if let ModuleItem::Stmt(stmt) = module_item {
+            if !matches!(stmt, Stmt::Decl(_)) {
+                stmt.visit_with(&mut finder);
+            }
+        }
+    }
+    finder.names
+}
+
+#[derive(Default)]
+struct AssignedBindings {
+    names: HashSet<String>,
 }
 
+impl Visit for AssignedBindings {
+    noop_visit_type!();
+
+    fn visit_function(&mut self, _: &Function) {}
+
+    fn visit_arrow_expr(&mut self, _: &ArrowExpr) {}
+
+    fn visit_class(&mut self, _: &Class) {}
+
+    fn visit_assign_expr(&mut self, n: &AssignExpr) {
+        match &n.left {
+            PatOrExpr::Expr(expr) => {
+                if let Expr::Ident(ident) = &**expr {
+                    self.names.insert(ident.sym.to_string());
+                }
+            }
+            PatOrExpr::Pat(pat) => match &**pat {
+                Pat::Expr(expr) => {
+                    if let Expr::Ident(ident) = &**expr {
+                        self.names.insert(ident.sym.to_string());
+                    }
+                }
+                pat => self.names.extend(
+                    find_pat_ids::<_, Id>(pat)
+                        .into_iter()
+                        .map(|(name, _)| name.to_string()),
+                ),
+            },
+        }
+        n.visit_children_with(self);
+    }
+
+    fn visit_update_expr(&mut self, n: &UpdateExpr) {
+        if let Expr::Ident(ident) = &*n.arg {
+            self.names.insert(ident.sym.to_string());
+        }
+        n.visit_children_with(self);
+    }
+}
+
+/// The sources of the module's `export * from` statements, in order.
+pub fn get_export_all_sources(module: &Module) -> Vec<String> {
+    module
+        .body
+        .iter()
+        .filter_map(|module_item| match module_item {
+            ModuleItem::ModuleDecl(ModuleDecl::ExportAll(export_all)) => {
+                Some(export_all.src.value.to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Clone)]
 pub struct ModuleDeclaration {
     pub exported: bool,
     pub declaration: Declaration,
@@ -28,7 +215,6 @@ fn get_name_from_module_export_name(name: &ModuleExportName) -> String {
 }
 
 fn get_module_declarations_from_module_item(
-    current_uri: String,
     module_item: ModuleItem,
 ) -> Vec<(String, ModuleDeclaration)> {
     match module_item {
@@ -40,6 +226,19 @@ fn get_module_declarations_from_module_item(
                     declaration: Declaration::FnExpr(func),
                 },
             )],
+            DefaultDecl::Class(class_expr) => vec![(
+                "default".to_string(),
+                ModuleDeclaration {
+                    exported: true,
+                    declaration: Declaration::Class(ClassDecl {
+                        ident: class_expr
+                            .ident
+                            .unwrap_or_else(|| Ident::new("default".into(), Default::default())),
+                        declare: false,
+                        class: class_expr.class,
+                    }),
+                },
+            )],
             _ => vec![],
         },
         ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(decl)) => match decl.decl {
@@ -50,6 +249,14 @@ fn get_module_declarations_from_module_item(
                     declaration: Declaration::FnDecl(fn_decl),
                 },
             )],
+            Decl::Class(class_decl) => vec![(
+                class_decl.ident.sym.to_string(),
+                ModuleDeclaration {
+                    exported: true,
+                    declaration: Declaration::Class(class_decl),
+                },
+            )],
+            Decl::Var(var_decl) => get_var_declarations(&var_decl, true),
             _ => vec![],
         },
         ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(decl)) => decl
@@ -61,19 +268,30 @@ fn get_module_declarations_from_module_item(
                         Some(ref exported) => exported,
                         None => &n.orig,
                     }),
+                    ModuleDeclaration {
+                        exported: true,
+                        declaration: match decl.src {
+                            Some(ref src) => Declaration::FuneeIdentifier(FuneeIdentifier {
+                                name: get_name_from_module_export_name(&n.orig),
+                                uri: src.value.to_string(),
+                            }),
+                            None => {
+                                Declaration::LocalAlias(get_name_from_module_export_name(&n.orig))
+                            }
+                        },
+                    },
+                )),
+                ExportSpecifier::Namespace(n) => Some((
+                    get_name_from_module_export_name(&n.name),
                     ModuleDeclaration {
                         exported: true,
                         declaration: Declaration::FuneeIdentifier(FuneeIdentifier {
-                            name: get_name_from_module_export_name(&n.orig),
-                            uri: match decl.src {
-                                Some(ref src) => src.value.to_string(),
-                                None => current_uri.clone(),
-                            },
+                            name: NAMESPACE.to_string(),
+                            uri: decl.src.as_ref()?.value.to_string(),
                         }),
                     },
                 )),
                 ExportSpecifier::Default(_) => None,
-                ExportSpecifier::Namespace(_) => None,
             })
             .collect(),
         ModuleItem::ModuleDecl(ModuleDecl::Import(decl)) => decl
@@ -89,7 +307,7 @@ fn get_module_declarations_from_module_item(
                                 Some(ref imported) => get_name_from_module_export_name(imported),
                                 None => n.local.sym.to_string(),
                             },
-                            uri: get_import_decl_uri(&current_uri, &decl),
+                            uri: decl.src.value.to_string(),
                         }),
                     },
                 )),
@@ -99,14 +317,32 @@ fn get_module_declarations_from_module_item(
                         exported: false,
                         declaration: Declaration::FuneeIdentifier(FuneeIdentifier {
                             name: "default".to_string(),
-                            uri: get_import_decl_uri(&current_uri, &decl),
+                            uri: decl.src.value.to_string(),
+                        }),
+                    },
+                )),
+                ImportSpecifier::Namespace(n) => Some((
+                    n.local.sym.to_string(),
+                    ModuleDeclaration {
+                        exported: false,
+                        declaration: Declaration::FuneeIdentifier(FuneeIdentifier {
+                            name: NAMESPACE.to_string(),
+                            uri: decl.src.value.to_string(),
                         }),
                     },
                 )),
-                ImportSpecifier::Namespace(_) => None,
             })
             .collect(),
-        ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(_)) => vec![],
+        ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(node)) => vec![(
+            "default".to_string(),
+            ModuleDeclaration {
+                exported: true,
+                declaration: match *node.expr {
+                    Expr::Ident(ident) => Declaration::LocalAlias(ident.sym.to_string()),
+                    expr => Declaration::VarInit(expr, None),
+                },
+            },
+        )],
         ModuleItem::Stmt(Stmt::Decl(Decl::Fn(func))) => vec![(
             func.ident.sym.to_string(),
             ModuleDeclaration {
@@ -114,14 +350,39 @@ fn get_module_declarations_from_module_item(
                 declaration: Declaration::FnDecl(func),
             },
         )],
+        ModuleItem::Stmt(Stmt::Decl(Decl::Class(class_decl))) => vec![(
+            class_decl.ident.sym.to_string(),
+            ModuleDeclaration {
+                exported: false,
+                declaration: Declaration::Class(class_decl),
+            },
+        )],
+        ModuleItem::Stmt(Stmt::Decl(Decl::Var(var_decl))) => get_var_declarations(&var_decl, false),
         _ => vec![],
     }
 }
 
-fn get_import_decl_uri(current_uri: &String, decl: &swc_ecma_ast::ImportDecl) -> String {
-    Path::new(current_uri)
-        .join(Path::new(&decl.src.value.to_string().clone()))
-        .to_str()
-        .unwrap()
-        .to_string()
+/// Declares each binding of `var_decl` as its initializer, or as `undefined`
+/// when it has none. Destructured bindings are left out.
+fn get_var_declarations(var_decl: &VarDecl, exported: bool) -> Vec<(String, ModuleDeclaration)> {
+    var_decl
+        .decls
+        .iter()
+        .filter_map(|declarator| match &declarator.name {
+            Pat::Ident(binding) => Some((
+                binding.id.sym.to_string(),
+                ModuleDeclaration {
+                    exported,
+                    declaration: Declaration::VarInit(
+                        match &declarator.init {
+                            Some(init) => (**init).clone(),
+                            None => Expr::Ident(Ident::new("undefined".into(), DUMMY_SP)),
+                        },
+                        Some(var_decl.kind),
+                    ),
+                },
+            )),
+            _ => None,
+        })
+        .collect()
 }
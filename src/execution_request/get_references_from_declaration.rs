@@ -1,7 +1,7 @@
 use super::declaration::Declaration;
 use std::collections::{HashMap, HashSet};
-use swc_common::{Globals, Mark, GLOBALS};
-use swc_ecma_ast::Ident;
+use swc_common::{BytePos, Globals, Mark, GLOBALS};
+use swc_ecma_ast::{Ident, JSXAttrName, JSXElementName, JSXMemberExpr};
 use swc_ecma_transforms_base::resolver;
 use swc_ecma_visit::{
     self, noop_visit_mut_type, noop_visit_type, Visit, VisitMut, VisitMutWith, VisitWith,
@@ -14,9 +14,11 @@ pub fn get_references_from_declaration(
     match decl {
         Declaration::FnDecl(n) => get_references_from_ast(&mut n.function, unresolved_mark),
         Declaration::FnExpr(n) => get_references_from_ast(n, unresolved_mark),
+        Declaration::Class(n) => get_references_from_ast(&mut *n.class, unresolved_mark),
         Declaration::Expr(n) => get_references_from_ast(n, unresolved_mark),
-        Declaration::FuneeIdentifier(_) => HashSet::new(),
-        Declaration::HostFn(_) => HashSet::new(),
+        Declaration::VarInit(n, _) => get_references_from_ast(n, unresolved_mark),
+        Declaration::FuneeIdentifier(_) | Declaration::LocalAlias(_) => HashSet::new(),
+        Declaration::HostFn { .. } => HashSet::new(),
     }
 }
 
@@ -34,6 +36,28 @@ impl Visit for ResolveReferences {
             self.references.insert(n.sym.to_string());
         }
     }
+
+    fn visit_jsx_element_name(&mut self, n: &JSXElementName) {
+        if !is_intrinsic_element(n) {
+            n.visit_children_with(self);
+        }
+    }
+
+    fn visit_jsx_member_expr(&mut self, n: &JSXMemberExpr) {
+        n.obj.visit_with(self);
+    }
+
+    fn visit_jsx_attr_name(&mut self, _: &JSXAttrName) {}
+}
+
+/// Lowercase JSX element names such as `<div>` refer to intrinsic elements,
+/// not to bindings in scope.
+fn is_intrinsic_element(n: &JSXElementName) -> bool {
+    match n {
+        JSXElementName::Ident(ident) => ident.sym.starts_with(|c: char| c.is_ascii_lowercase()),
+        JSXElementName::JSXMemberExpr(_) => false,
+        JSXElementName::JSXNamespacedName(_) => true,
+    }
 }
 
 fn get_references_from_ast<T: Clone + VisitMutWith<dyn VisitMut> + VisitWith<ResolveReferences>>(
@@ -56,7 +80,7 @@ fn get_references_from_ast<T: Clone + VisitMutWith<dyn VisitMut> + VisitWith<Res
 
 pub fn rename_references_in_declaration(
     decl: &mut Declaration,
-    to_replace: HashMap<String, String>,
+    to_replace: HashMap<(String, Option<BytePos>), String>,
     unresolved_mark: (&Globals, Mark),
 ) {
     match decl {
@@ -64,9 +88,13 @@ pub fn rename_references_in_declaration(
             rename_references_in_ast(&mut n.function, to_replace, unresolved_mark)
         }
         Declaration::FnExpr(n) => rename_references_in_ast(n, to_replace, unresolved_mark),
+        Declaration::Class(n) => {
+            rename_references_in_ast(&mut *n.class, to_replace, unresolved_mark)
+        }
         Declaration::Expr(n) => rename_references_in_ast(n, to_replace, unresolved_mark),
-        Declaration::FuneeIdentifier(_) => {}
-        Declaration::HostFn(_) => {}
+        Declaration::VarInit(n, _) => rename_references_in_ast(n, to_replace, unresolved_mark),
+        Declaration::FuneeIdentifier(_) | Declaration::LocalAlias(_) => {}
+        Declaration::HostFn { .. } => {}
     };
 }
 
@@ -74,7 +102,7 @@ fn rename_references_in_ast<
     T: Clone + VisitMutWith<dyn VisitMut> + VisitWith<ResolveReferences>,
 >(
     ast: &mut T,
-    to_replace: HashMap<String, String>,
+    to_replace: HashMap<(String, Option<BytePos>), String>,
     unresolved_mark: (&Globals, Mark),
 ) {
     GLOBALS.set(unresolved_mark.0, || {
@@ -87,7 +115,7 @@ fn rename_references_in_ast<
 
 struct RenameReferences {
     pub unresolved_mark: Mark,
-    pub to_replace: HashMap<String, String>,
+    pub to_replace: HashMap<(String, Option<BytePos>), String>,
 }
 
 impl<'a> VisitMut for RenameReferences {
@@ -95,10 +123,26 @@ impl<'a> VisitMut for RenameReferences {
 
     fn visit_mut_ident(&mut self, n: &mut Ident) {
         if n.span.has_mark(self.unresolved_mark) {
-            let name = n.sym.as_ref();
-            if let Some(to_replace) = self.to_replace.get(name) {
+            let name = n.sym.to_string();
+            let to_replace = self
+                .to_replace
+                .get(&(name.clone(), Some(n.span.lo)))
+                .or_else(|| self.to_replace.get(&(name, None)));
+            if let Some(to_replace) = to_replace {
                 n.sym = to_replace.clone().into();
             }
         }
     }
+
+    fn visit_mut_jsx_element_name(&mut self, n: &mut JSXElementName) {
+        if !is_intrinsic_element(n) {
+            n.visit_mut_children_with(self);
+        }
+    }
+
+    fn visit_mut_jsx_member_expr(&mut self, n: &mut JSXMemberExpr) {
+        n.obj.visit_mut_with(self);
+    }
+
+    fn visit_mut_jsx_attr_name(&mut self, _: &mut JSXAttrName) {}
 }
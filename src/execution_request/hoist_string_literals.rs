@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use swc_ecma_ast::{
+    BindingIdent, Decl, Expr, ExprStmt, Ident, Lit, Module, ModuleItem, Pat, Stmt, VarDecl,
+    VarDeclKind, VarDeclarator,
+};
+use swc_ecma_visit::{
+    noop_visit_mut_type, noop_visit_type, Visit, VisitMut, VisitMutWith, VisitWith,
+};
+
+pub struct StringLiteralHoisting {
+    pub min_length: usize,
+    pub min_occurrences: usize,
+}
+
+impl Default for StringLiteralHoisting {
+    fn default() -> Self {
+        Self {
+            min_length: 32,
+            min_occurrences: 2,
+        }
+    }
+}
+
+pub fn hoist_string_literals(module: &mut Module, options: &StringLiteralHoisting) {
+    let mut counter = CountStringLiterals {
+        min_length: options.min_length,
+        occurrences: HashMap::new(),
+    };
+    module.visit_with(&mut counter);
+
+    let mut hoisted: Vec<String> = counter
+        .occurrences
+        .into_iter()
+        .filter(|(_, count)| *count >= options.min_occurrences)
+        .map(|(value, _)| value)
+        .collect();
+    if hoisted.is_empty() {
+        return;
+    }
+    hoisted.sort();
+
+    let names: HashMap<String, String> = hoisted
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            (
+                value.clone(),
+                "string_literal_".to_string() + &index.to_string(),
+            )
+        })
+        .collect();
+    module.visit_mut_with(&mut ReplaceStringLiterals { names: &names });
+
+    let declarations: Vec<ModuleItem> = hoisted
+        .into_iter()
+        .map(|value| {
+            let name = names.get(&value).unwrap().clone();
+            ModuleItem::Stmt(Stmt::Decl(Decl::Var(VarDecl {
+                span: Default::default(),
+                kind: VarDeclKind::Var,
+                declare: false,
+                decls: vec![VarDeclarator {
+                    span: Default::default(),
+                    name: Pat::Ident(BindingIdent::from(Ident::new(
+                        name.into(),
+                        Default::default(),
+                    ))),
+                    init: Some(Box::new(Expr::Lit(Lit::Str(value.into())))),
+                    definite: false,
+                }],
+            })))
+        })
+        .collect();
+    module.body.splice(0..0, declarations);
+}
+
+fn is_directive(n: &ExprStmt) -> bool {
+    matches!(*n.expr, Expr::Lit(Lit::Str(_)))
+}
+
+struct CountStringLiterals {
+    min_length: usize,
+    occurrences: HashMap<String, usize>,
+}
+
+impl Visit for CountStringLiterals {
+    noop_visit_type!();
+
+    fn visit_expr_stmt(&mut self, n: &ExprStmt) {
+        if !is_directive(n) {
+            n.visit_children_with(self);
+        }
+    }
+
+    fn visit_expr(&mut self, n: &Expr) {
+        if let Expr::Lit(Lit::Str(s)) = n {
+            if s.value.len() >= self.min_length {
+                *self.occurrences.entry(s.value.to_string()).or_default() += 1;
+            }
+        }
+        n.visit_children_with(self);
+    }
+}
+
+struct ReplaceStringLiterals<'a> {
+    names: &'a HashMap<String, String>,
+}
+
+impl<'a> VisitMut for ReplaceStringLiterals<'a> {
+    noop_visit_mut_type!();
+
+    fn visit_mut_expr_stmt(&mut self, n: &mut ExprStmt) {
+        if !is_directive(n) {
+            n.visit_mut_children_with(self);
+        }
+    }
+
+    fn visit_mut_expr(&mut self, n: &mut Expr) {
+        if let Expr::Lit(Lit::Str(s)) = n {
+            if let Some(name) = self.names.get(&*s.value) {
+                *n = Expr::Ident(Ident::new(name.clone().into(), s.span));
+                return;
+            }
+        }
+        n.visit_mut_children_with(self);
+    }
+}
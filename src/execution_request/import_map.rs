@@ -0,0 +1,39 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Maps bare or prefixed specifiers to the modules they should load, with
+/// `scopes` overriding `imports` for modules under a given prefix.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct ImportMap {
+    pub imports: HashMap<String, String>,
+    pub scopes: HashMap<String, HashMap<String, String>>,
+}
+
+impl ImportMap {
+    pub fn resolve(&self, referrer: &str, specifier: &str) -> Option<String> {
+        let mut scopes: Vec<_> = self
+            .scopes
+            .iter()
+            .filter(|(prefix, _)| matches_prefix(prefix, referrer))
+            .collect();
+        scopes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        scopes
+            .into_iter()
+            .map(|(_, imports)| imports)
+            .chain([&self.imports])
+            .find_map(|imports| resolve_imports_match(imports, specifier))
+    }
+}
+
+fn matches_prefix(prefix: &str, value: &str) -> bool {
+    value == prefix || (prefix.ends_with('/') && value.starts_with(prefix))
+}
+
+fn resolve_imports_match(imports: &HashMap<String, String>, specifier: &str) -> Option<String> {
+    imports
+        .iter()
+        .filter(|(key, _)| matches_prefix(key, specifier))
+        .max_by_key(|(key, _)| key.len())
+        .map(|(key, target)| target.clone() + &specifier[key.len()..])
+}
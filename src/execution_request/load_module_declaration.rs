@@ -1,11 +1,367 @@
-use super::get_module_declarations::{get_module_declarations, ModuleDeclaration};
-use crate::{funee_identifier::FuneeIdentifier, load_module::load_module};
-use std::{path::PathBuf, rc::Rc};
-use swc_common::SourceMap;
-
-pub fn load_declaration(cm: &Rc<SourceMap>, t: &FuneeIdentifier) -> Option<ModuleDeclaration> {
-    let module = load_module(cm, PathBuf::from(t.uri.as_str()));
-    let mut module_declarations = get_module_declarations(module.clone());
-    let declaration = module_declarations.remove(t.name.as_str());
-    declaration
+use super::{
+    asset_loader::{load_asset, load_json, AssetLoader, CODE_EXTENSIONS},
+    declaration::Declaration,
+    get_cjs_module_declarations::{get_cjs_module_declarations, is_cjs_module},
+    get_module_declarations::{
+        get_export_all_sources, get_module_declarations, ModuleDeclaration, ModuleDeclarations,
+        NAMESPACE,
+    },
+    import_map::ImportMap,
+    resolve_uri::{Resolver, DEFAULT_RESOLVE_EXTENSIONS},
+};
+use crate::{
+    funee_identifier::FuneeIdentifier,
+    load_module::{ModuleLoader, ParseOptions, SourceTransforms},
+};
+use deno_core::error::{generic_error, AnyError};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::PathBuf,
+};
+use swc_ecma_ast::{
+    ComputedPropName, Expr, Ident, KeyValueProp, Lit, Module, ObjectLit, Prop, PropName,
+    PropOrSpread,
+};
+
+const TYPE_DECLARATION_EXTENSIONS: [&str; 3] = [".d.ts", ".d.mts", ".d.cts"];
+
+/// The declarations of a parsed module, so that it is parsed at most once per
+/// `SourceGraph::load`.
+pub struct LoadedModule {
+    declarations: HashMap<String, ModuleDeclaration>,
+    unsupported: HashMap<String, &'static str>,
+    export_all_sources: Vec<String>,
+}
+
+pub type ModuleCache = HashMap<String, LoadedModule>;
+
+/// What loading declarations needs besides the modules loaded so far.
+pub struct DeclarationLoader<'a> {
+    pub module_loader: &'a ModuleLoader,
+    pub asset_loaders: &'a HashMap<String, AssetLoader>,
+    pub source_transforms: &'a SourceTransforms,
+    pub parse_options: &'a ParseOptions,
+    pub resolver: &'a Resolver<'a>,
+    /// When set, only files with one of these extensions may be loaded.
+    pub allowed_extensions: &'a Option<HashSet<String>>,
+}
+
+impl DeclarationLoader<'_> {
+    pub fn load_declaration(
+        &self,
+        t: &FuneeIdentifier,
+        modules: &mut ModuleCache,
+    ) -> Result<Option<ModuleDeclaration>, AnyError> {
+        self.load_declaration_once(t, modules, &mut HashSet::from([t.uri.clone()]))
+    }
+
+    /// Loads `t`, following only the star exports of modules not in
+    /// `visited`, so that star exports cycling back to a module end there.
+    fn load_declaration_once(
+        &self,
+        t: &FuneeIdentifier,
+        modules: &mut ModuleCache,
+        visited: &mut HashSet<String>,
+    ) -> Result<Option<ModuleDeclaration>, AnyError> {
+        self.check_extension(&t.uri)?;
+        if is_type_only(&t.uri) {
+            return Err(generic_error(format!(
+                "{} is a type-only module, it has no runtime export '{}'",
+                t.uri, t.name
+            )));
+        }
+        let path = PathBuf::from(t.uri.as_str());
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let source_transform =
+            extension.and_then(|extension| self.source_transforms.get(extension));
+        if let (Some(extension), None) = (extension, source_transform) {
+            if let Some(asset_loader) = self.asset_loaders.get(extension) {
+                if t.name != "default" {
+                    return Ok(None);
+                }
+                return Ok(Some(ModuleDeclaration {
+                    exported: true,
+                    declaration: Declaration::VarInit(
+                        load_asset(self.module_loader, &path, asset_loader)?,
+                        None,
+                    ),
+                }));
+            }
+            if extension == "json" {
+                if t.name != "default" {
+                    return Ok(None);
+                }
+                return Ok(Some(ModuleDeclaration {
+                    exported: true,
+                    declaration: Declaration::VarInit(
+                        load_json(&self.module_loader.cm, &path)?,
+                        None,
+                    ),
+                }));
+            }
+            if !CODE_EXTENSIONS.contains(&extension) {
+                return Err(generic_error(format!(
+                    "No loader is configured for \".{}\" files, cannot import {}",
+                    extension, t.uri
+                )));
+            }
+        }
+
+        self.load_module_into_cache(&t.uri, modules)?;
+        if t.name == NAMESPACE {
+            let mut names = self.collect_exported_names(&t.uri, modules, &mut HashSet::new())?;
+            if matches!(
+                modules[&t.uri].declarations.get("default"),
+                Some(ModuleDeclaration { exported: true, .. })
+            ) {
+                names.insert("default".to_string());
+            }
+            return Ok(Some(ModuleDeclaration {
+                exported: true,
+                declaration: Declaration::VarInit(namespace_object(names), None),
+            }));
+        }
+        if let Some(declaration) = modules[&t.uri].declarations.get(&t.name) {
+            return Ok(Some(declaration.clone()));
+        }
+        if let Some(reason) = modules[&t.uri].unsupported.get(&t.name) {
+            return Err(generic_error(format!(
+                "Cannot use '{}' from {}: {}",
+                t.name, t.uri, reason
+            )));
+        }
+        if t.name == "default" {
+            return Ok(None);
+        }
+
+        // Star exports reaching the same declaration along different paths
+        // export it once, rather than conflicting.
+        let mut star_export: Option<(String, FuneeIdentifier)> = None;
+        for source in modules[&t.uri].export_all_sources.clone() {
+            let uri = self.resolver.resolve_uri(&t.uri, &source);
+            if is_type_only(&uri) || !visited.insert(uri.clone()) {
+                continue;
+            }
+            let candidate = FuneeIdentifier {
+                name: t.name.clone(),
+                uri,
+            };
+            let target = match self.load_declaration_once(&candidate, modules, visited)? {
+                Some(ModuleDeclaration {
+                    exported: true,
+                    declaration: Declaration::FuneeIdentifier(identifier),
+                }) => FuneeIdentifier {
+                    uri: self.resolver.resolve_uri(&candidate.uri, &identifier.uri),
+                    name: identifier.name,
+                },
+                Some(ModuleDeclaration {
+                    exported: true,
+                    declaration: Declaration::LocalAlias(name),
+                }) => FuneeIdentifier {
+                    name,
+                    uri: candidate.uri,
+                },
+                Some(ModuleDeclaration { exported: true, .. }) => candidate,
+                _ => continue,
+            };
+            match &star_export {
+                Some((_, found)) if *found != target => {
+                    return Err(generic_error(format!(
+                        "Conflicting star exports for name '{}' in {}",
+                        t.name, t.uri
+                    )))
+                }
+                Some(_) => {}
+                None => star_export = Some((source, target)),
+            }
+        }
+        Ok(star_export.map(|(source, _)| ModuleDeclaration {
+            exported: true,
+            declaration: Declaration::FuneeIdentifier(FuneeIdentifier {
+                name: t.name.clone(),
+                uri: source,
+            }),
+        }))
+    }
+
+    fn check_extension(&self, uri: &str) -> Result<(), AnyError> {
+        let allowed_extensions = match self.allowed_extensions {
+            Some(allowed_extensions) => allowed_extensions,
+            None => return Ok(()),
+        };
+        let file_name = uri.rsplit('/').next().unwrap_or(uri);
+        match file_name.rsplit_once('.') {
+            Some((_, extension)) if allowed_extensions.contains(extension) => Ok(()),
+            Some((_, extension)) => Err(generic_error(format!(
+                "Importing \".{}\" files is not allowed, cannot import {}",
+                extension, uri
+            ))),
+            None => Err(generic_error(format!(
+                "Importing files without an extension is not allowed, cannot import {}",
+                uri
+            ))),
+        }
+    }
+
+    /// Parses the entry file run as a script. Its declarations are cached
+    /// along the way, so that the statements referencing them don't parse it
+    /// again.
+    pub fn load_script(&self, uri: &str, modules: &mut ModuleCache) -> Result<Module, AnyError> {
+        self.check_extension(uri)?;
+        if is_type_only(uri) {
+            return Err(generic_error(format!(
+                "{} is a type-only module, it cannot run as a script",
+                uri
+            )));
+        }
+        let module = self.parse_module(uri)?;
+        self.cache_module(uri, &module, modules)?;
+        Ok(module)
+    }
+
+    fn load_module_into_cache(&self, uri: &str, modules: &mut ModuleCache) -> Result<(), AnyError> {
+        if modules.contains_key(uri) {
+            return Ok(());
+        }
+        let module = self.parse_module(uri)?;
+        self.cache_module(uri, &module, modules)
+    }
+
+    fn parse_module(&self, uri: &str) -> Result<Module, AnyError> {
+        let source_transform = PathBuf::from(uri)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.source_transforms.get(extension));
+        self.module_loader
+            .load_module(PathBuf::from(uri), source_transform, self.parse_options)
+    }
+
+    fn cache_module(
+        &self,
+        uri: &str,
+        module: &Module,
+        modules: &mut ModuleCache,
+    ) -> Result<(), AnyError> {
+        let (declarations, unsupported) = if is_cjs_module(uri, module) {
+            (get_cjs_module_declarations(module), HashMap::new())
+        } else {
+            let ModuleDeclarations {
+                declarations,
+                unsupported,
+            } = get_module_declarations(module.clone())
+                .map_err(|error| generic_error(format!("{} in {}", error, uri)))?;
+            (declarations, unsupported)
+        };
+        modules.insert(
+            uri.to_string(),
+            LoadedModule {
+                declarations,
+                unsupported,
+                export_all_sources: get_export_all_sources(module),
+            },
+        );
+        Ok(())
+    }
+
+    /// The names other than `default` that `uri` exports, including those of
+    /// the modules it star exports from.
+    fn collect_exported_names(
+        &self,
+        uri: &str,
+        modules: &mut ModuleCache,
+        visited: &mut HashSet<String>,
+    ) -> Result<BTreeSet<String>, AnyError> {
+        let mut names = BTreeSet::new();
+        if is_type_only(uri) || !visited.insert(uri.to_string()) {
+            return Ok(names);
+        }
+        self.check_extension(uri)?;
+        self.load_module_into_cache(uri, modules)?;
+        let loaded_module = &modules[uri];
+        names.extend(
+            loaded_module
+                .declarations
+                .iter()
+                .filter(|(name, declaration)| declaration.exported && name.as_str() != "default")
+                .map(|(name, _)| name.clone()),
+        );
+        for source in loaded_module.export_all_sources.clone() {
+            names.extend(self.collect_exported_names(
+                &self.resolver.resolve_uri(uri, &source),
+                modules,
+                visited,
+            )?);
+        }
+        Ok(names)
+    }
+}
+
+/// Whether `uri` is a type declaration file, which exports nothing at runtime.
+fn is_type_only(uri: &str) -> bool {
+    TYPE_DECLARATION_EXTENSIONS
+        .iter()
+        .any(|extension| uri.ends_with(extension))
+}
+
+/// An object with a property for each of `names`, each referencing the
+/// declaration exported under that name.
+fn namespace_object(names: BTreeSet<String>) -> Expr {
+    Expr::Object(ObjectLit {
+        span: Default::default(),
+        props: names
+            .into_iter()
+            .map(|name| {
+                PropOrSpread::Prop(Box::new(Prop::KeyValue(KeyValueProp {
+                    key: namespace_key(&name),
+                    value: Box::new(Expr::Ident(Ident::new(name.into(), Default::default()))),
+                })))
+            })
+            .collect(),
+    })
+}
+
+/// Names that aren't identifiers, such as those of `export { x as "a-b" }`,
+/// are written as strings, and `__proto__` is computed so that it defines a
+/// property rather than the prototype.
+fn namespace_key(name: &str) -> PropName {
+    if name == "__proto__" {
+        PropName::Computed(ComputedPropName {
+            span: Default::default(),
+            expr: Box::new(Expr::Lit(Lit::Str(name.into()))),
+        })
+    } else if Ident::verify_symbol(name).is_ok() {
+        PropName::Ident(Ident::new(name.into(), Default::default()))
+    } else {
+        PropName::Str(name.into())
+    }
+}
+
+/// Resolves many identifiers at once, parsing each module they name at most
+/// once. Identifiers their module doesn't declare are left out.
+pub fn resolve_all(
+    module_loader: &ModuleLoader,
+    identifiers: &[FuneeIdentifier],
+) -> Result<HashMap<FuneeIdentifier, Declaration>, AnyError> {
+    let mut modules = ModuleCache::new();
+    let mut declarations = HashMap::new();
+    let resolver = Resolver {
+        cm: &module_loader.cm,
+        import_map: &ImportMap::default(),
+        extensions: &DEFAULT_RESOLVE_EXTENSIONS.map(String::from),
+    };
+    let loader = DeclarationLoader {
+        module_loader,
+        asset_loaders: &HashMap::new(),
+        source_transforms: &HashMap::new(),
+        parse_options: &Default::default(),
+        resolver: &resolver,
+        allowed_extensions: &None,
+    };
+    for identifier in identifiers {
+        let module_declaration = loader.load_declaration(identifier, &mut modules)?;
+        if let Some(module_declaration) = module_declaration {
+            declarations.insert(identifier.clone(), module_declaration.declaration);
+        }
+    }
+    Ok(declarations)
 }
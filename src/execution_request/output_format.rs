@@ -0,0 +1,198 @@
+use swc_ecma_ast::{
+    ArrayLit, AssignExpr, AssignOp, BinExpr, BinaryOp, BlockStmt, CallExpr, Callee,
+    ComputedPropName, ExportDefaultExpr, Expr, ExprOrSpread, ExprStmt, FnExpr, Function, Ident,
+    IfStmt, Lit, MemberExpr, MemberProp, ModuleDecl, ModuleItem, Param, ParenExpr, Pat, PatOrExpr,
+    ReturnStmt, Stmt, ThisExpr, UnaryExpr, UnaryOp,
+};
+
+#[derive(Default)]
+pub enum OutputFormat {
+    #[default]
+    Script,
+    Esm,
+    Cjs,
+    Umd {
+        global_name: String,
+    },
+}
+
+pub fn apply_output_format(
+    declarations: Vec<ModuleItem>,
+    entry: Expr,
+    format: &OutputFormat,
+) -> Vec<ModuleItem> {
+    let mut module_items = declarations;
+    match format {
+        OutputFormat::Script => module_items.push(ModuleItem::Stmt(expr_stmt(entry))),
+        OutputFormat::Esm => module_items.push(ModuleItem::ModuleDecl(
+            ModuleDecl::ExportDefaultExpr(ExportDefaultExpr {
+                span: Default::default(),
+                expr: Box::new(entry),
+            }),
+        )),
+        OutputFormat::Cjs => module_items.push(ModuleItem::Stmt(expr_stmt(assign(
+            member(ident("module"), "exports"),
+            entry,
+        )))),
+        OutputFormat::Umd { global_name } => {
+            let mut factory_body: Vec<Stmt> = module_items
+                .into_iter()
+                .map(|module_item| match module_item {
+                    ModuleItem::Stmt(stmt) => stmt,
+                    ModuleItem::ModuleDecl(_) => unreachable!(),
+                })
+                .collect();
+            factory_body.push(Stmt::Return(ReturnStmt {
+                span: Default::default(),
+                arg: Some(Box::new(entry)),
+            }));
+            module_items = vec![ModuleItem::Stmt(expr_stmt(call(
+                Expr::Paren(ParenExpr {
+                    span: Default::default(),
+                    expr: Box::new(function(vec!["root", "factory"], umd_loader(global_name))),
+                }),
+                vec![
+                    Expr::This(ThisExpr {
+                        span: Default::default(),
+                    }),
+                    function(vec![], factory_body),
+                ],
+            )))];
+        }
+    }
+    module_items
+}
+
+fn umd_loader(global_name: &str) -> Vec<Stmt> {
+    let call_factory = || call(ident("factory"), vec![]);
+    vec![Stmt::If(IfStmt {
+        span: Default::default(),
+        test: Box::new(and(
+            type_of_is("module", "object"),
+            member(ident("module"), "exports"),
+        )),
+        cons: Box::new(expr_stmt(assign(
+            member(ident("module"), "exports"),
+            call_factory(),
+        ))),
+        alt: Some(Box::new(Stmt::If(IfStmt {
+            span: Default::default(),
+            test: Box::new(and(
+                type_of_is("define", "function"),
+                member(ident("define"), "amd"),
+            )),
+            cons: Box::new(expr_stmt(call(
+                ident("define"),
+                vec![
+                    Expr::Array(ArrayLit {
+                        span: Default::default(),
+                        elems: vec![],
+                    }),
+                    ident("factory"),
+                ],
+            ))),
+            alt: Some(Box::new(expr_stmt(assign(
+                Expr::Member(MemberExpr {
+                    span: Default::default(),
+                    obj: Box::new(ident("root")),
+                    prop: MemberProp::Computed(ComputedPropName {
+                        span: Default::default(),
+                        expr: Box::new(Expr::Lit(Lit::Str(global_name.into()))),
+                    }),
+                }),
+                call_factory(),
+            )))),
+        }))),
+    })]
+}
+
+fn ident(name: &str) -> Expr {
+    Expr::Ident(Ident::new(name.into(), Default::default()))
+}
+
+fn member(obj: Expr, prop: &str) -> Expr {
+    Expr::Member(MemberExpr {
+        span: Default::default(),
+        obj: Box::new(obj),
+        prop: MemberProp::Ident(Ident::new(prop.into(), Default::default())),
+    })
+}
+
+fn call(callee: Expr, args: Vec<Expr>) -> Expr {
+    Expr::Call(CallExpr {
+        span: Default::default(),
+        callee: Callee::Expr(Box::new(callee)),
+        args: args
+            .into_iter()
+            .map(|arg| ExprOrSpread {
+                spread: None,
+                expr: Box::new(arg),
+            })
+            .collect(),
+        type_args: None,
+    })
+}
+
+fn assign(left: Expr, right: Expr) -> Expr {
+    Expr::Assign(AssignExpr {
+        span: Default::default(),
+        op: AssignOp::Assign,
+        left: PatOrExpr::Expr(Box::new(left)),
+        right: Box::new(right),
+    })
+}
+
+fn and(left: Expr, right: Expr) -> Expr {
+    Expr::Bin(BinExpr {
+        span: Default::default(),
+        op: BinaryOp::LogicalAnd,
+        left: Box::new(left),
+        right: Box::new(right),
+    })
+}
+
+fn type_of_is(name: &str, type_name: &str) -> Expr {
+    Expr::Bin(BinExpr {
+        span: Default::default(),
+        op: BinaryOp::EqEqEq,
+        left: Box::new(Expr::Unary(UnaryExpr {
+            span: Default::default(),
+            op: UnaryOp::TypeOf,
+            arg: Box::new(ident(name)),
+        })),
+        right: Box::new(Expr::Lit(Lit::Str(type_name.into()))),
+    })
+}
+
+fn function(params: Vec<&str>, stmts: Vec<Stmt>) -> Expr {
+    Expr::Fn(FnExpr {
+        ident: None,
+        function: Function {
+            params: params
+                .into_iter()
+                .map(|param| Param {
+                    span: Default::default(),
+                    decorators: Default::default(),
+                    pat: Pat::Ident(Ident::new(param.into(), Default::default()).into()),
+                })
+                .collect(),
+            decorators: Default::default(),
+            span: Default::default(),
+            body: Some(BlockStmt {
+                span: Default::default(),
+                stmts,
+            }),
+            is_generator: false,
+            is_async: false,
+            type_params: None,
+            return_type: None,
+        },
+    })
+}
+
+fn expr_stmt(expr: Expr) -> Stmt {
+    Stmt::Expr(ExprStmt {
+        span: Default::default(),
+        expr: Box::new(expr),
+    })
+}
@@ -0,0 +1,91 @@
+use super::import_map::ImportMap;
+use deno_core::url::Url;
+use relative_path::RelativePath;
+use std::path::{Component, Path, PathBuf};
+use swc_common::SourceMap;
+
+pub const DEFAULT_RESOLVE_EXTENSIONS: [&str; 4] = ["ts", "tsx", "js", "mjs"];
+
+pub struct Resolver<'a> {
+    pub cm: &'a SourceMap,
+    pub import_map: &'a ImportMap,
+    /// Extensions tried, in order, when a specifier doesn't name an existing
+    /// file, first appended to it and then to an `index` file inside it.
+    pub extensions: &'a [String],
+}
+
+impl Resolver<'_> {
+    /// Resolves a specifier written in the module at `referrer`. Specifiers
+    /// written in a remote module resolve against its URL.
+    pub fn resolve_uri(&self, referrer: &str, specifier: &str) -> String {
+        let uri = if let Some(mapped) = self.import_map.resolve(referrer, specifier) {
+            normalize_uri(&mapped)
+        } else if is_remote(specifier) {
+            normalize_uri(specifier)
+        } else if is_remote(referrer) {
+            Url::parse(referrer)
+                .and_then(|base| base.join(specifier))
+                .map(|url| normalize_uri(url.as_str()))
+                .unwrap_or_else(|_| specifier.to_string())
+        } else if Path::new(specifier).is_absolute() {
+            normalize_uri(specifier)
+        } else {
+            let current_dir = Path::new(referrer).parent().unwrap().to_str().unwrap();
+            RelativePath::new(specifier)
+                .to_logical_path(current_dir)
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        self.probe(uri)
+    }
+
+    fn probe(&self, uri: String) -> String {
+        // The loader may not read from the host's filesystem, so it can't be
+        // asked whether `uri` is a directory, which exists too but names a
+        // module only through its index. The candidates are tried first
+        // instead, and `uri` is left as written when none exists.
+        if is_remote(&uri) {
+            return uri;
+        }
+        self.extensions
+            .iter()
+            .map(|extension| format!("{}.{}", uri, extension))
+            .chain(
+                self.extensions
+                    .iter()
+                    .map(|extension| format!("{}/index.{}", uri, extension)),
+            )
+            .find(|candidate| self.cm.file_exists(Path::new(candidate)))
+            .unwrap_or(uri)
+    }
+}
+
+fn is_remote(uri: &str) -> bool {
+    uri.starts_with("http://") || uri.starts_with("https://")
+}
+
+/// Collapses `.` and `..` segments and repeated separators so that equivalent
+/// paths name the same module. URLs are normalized by parsing them, dropping
+/// their fragment, which doesn't change the module they name.
+pub fn normalize_uri(uri: &str) -> String {
+    if is_remote(uri) {
+        return Url::parse(uri)
+            .map(|mut url| {
+                url.set_fragment(None);
+                url.to_string()
+            })
+            .unwrap_or_else(|_| uri.to_string());
+    }
+    let mut normalized = PathBuf::new();
+    for component in Path::new(uri).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized.to_str().unwrap().to_string()
+}
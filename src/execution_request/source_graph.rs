@@ -1,21 +1,32 @@
 use super::{
-    declaration::Declaration, get_references_from_declaration::get_references_from_declaration,
-    load_module_declaration::load_declaration,
+    asset_loader::AssetLoader,
+    declaration::Declaration,
+    get_references_from_declaration::get_references_from_declaration,
+    import_map::ImportMap,
+    load_module_declaration::DeclarationLoader,
+    resolve_uri::{normalize_uri, Resolver},
 };
-use crate::funee_identifier::FuneeIdentifier;
+use crate::{
+    funee_identifier::FuneeIdentifier,
+    load_module::{BinaryFileLoader, ModuleLoader, ParseOptions, SourceTransforms},
+};
+use deno_core::error::{generic_error, AnyError};
 use petgraph::{
     stable_graph::NodeIndex,
     visit::{Dfs, VisitMap},
     Graph,
 };
-use relative_path::RelativePath;
 use std::{
     collections::{HashMap, HashSet},
-    path::Path,
+    fmt,
+    path::PathBuf,
     rc::Rc,
 };
-use swc_common::{FileLoader, FilePathMapping, Globals, Mark, SourceMap, GLOBALS};
-use swc_ecma_ast::Expr;
+use swc_common::{BytePos, FileName, Globals, Mark, SourceMap, GLOBALS};
+use swc_ecma_ast::{
+    BlockStmt, CallExpr, Callee, Expr, FnExpr, Function, Module, ModuleDecl, ModuleItem, ParenExpr,
+    Stmt,
+};
 
 pub struct ReferencesMark {
     pub mark: Mark,
@@ -23,30 +34,256 @@ pub struct ReferencesMark {
 }
 
 pub struct SourceGraph {
-    pub graph: Graph<(String, Declaration), String>,
+    pub graph: Graph<(String, Declaration), (String, Option<BytePos>)>,
     pub root: NodeIndex,
     pub source_map: Rc<SourceMap>,
     pub references_mark: ReferencesMark,
+    pub warnings: Vec<String>,
+    pub resolution_trace: Vec<String>,
 }
 
 pub struct LoadParams {
     pub scope: String,
     pub expression: Expr,
+    pub entry_mode: EntryMode,
     pub host_functions: HashSet<FuneeIdentifier>,
-    pub file_loader: Box<dyn FileLoader + Sync + Send>,
+    /// The host functions whose ops are async, and are awaited when called.
+    pub async_host_functions: HashSet<FuneeIdentifier>,
+    pub file_loader: Box<dyn BinaryFileLoader + Sync + Send>,
+    pub limits: LoadLimits,
+    pub asset_loaders: HashMap<String, AssetLoader>,
+    pub source_transforms: SourceTransforms,
+    pub parse_options: ParseOptions,
+    pub on_unresolved: OnUnresolved,
+    pub import_map: ImportMap,
+    pub resolve_extensions: Vec<String>,
+    /// Names the runtime defines globally on top of `BUILTIN_GLOBALS`, which
+    /// are left unresolved too.
+    pub runtime_globals: HashSet<String>,
+    /// Records the hops each reference takes to its declaration in
+    /// `SourceGraph::resolution_trace`.
+    pub trace_resolution: bool,
+    /// When set, only files with one of these extensions may be imported.
+    pub allowed_extensions: Option<HashSet<String>>,
+}
+
+/// The globals of JavaScript itself and of the deno_core runtime. References
+/// to them are left unresolved unless their module declares the name.
+pub const BUILTIN_GLOBALS: &[&str] = &[
+    "AggregateError",
+    "Array",
+    "ArrayBuffer",
+    "Atomics",
+    "BigInt",
+    "BigInt64Array",
+    "BigUint64Array",
+    "Boolean",
+    "DataView",
+    "Date",
+    "Deno",
+    "Error",
+    "EvalError",
+    "FinalizationRegistry",
+    "Float32Array",
+    "Float64Array",
+    "Function",
+    "Infinity",
+    "Int16Array",
+    "Int32Array",
+    "Int8Array",
+    "Intl",
+    "JSON",
+    "Map",
+    "Math",
+    "NaN",
+    "Number",
+    "Object",
+    "Promise",
+    "Proxy",
+    "RangeError",
+    "ReferenceError",
+    "Reflect",
+    "RegExp",
+    "Set",
+    "SharedArrayBuffer",
+    "String",
+    "Symbol",
+    "SyntaxError",
+    "TypeError",
+    "URIError",
+    "Uint16Array",
+    "Uint32Array",
+    "Uint8Array",
+    "Uint8ClampedArray",
+    "WeakMap",
+    "WeakRef",
+    "WeakSet",
+    "arguments",
+    "console",
+    "decodeURI",
+    "decodeURIComponent",
+    "encodeURI",
+    "encodeURIComponent",
+    "eval",
+    "globalThis",
+    "isFinite",
+    "isNaN",
+    "parseFloat",
+    "parseInt",
+    "queueMicrotask",
+    "undefined",
+];
+
+/// What the program runs.
+#[derive(Default)]
+pub enum EntryMode {
+    /// Evaluates the expression in the scope module.
+    #[default]
+    Expression,
+    /// Runs the scope module's top-level statements in order, in place of
+    /// the expression.
+    Script,
+}
+
+/// What to do with a reference that is not declared in its module, such as a
+/// binding the runtime environment provides.
+#[derive(Default)]
+pub enum OnUnresolved {
+    #[default]
+    Error,
+    Warn,
+    Silent,
+}
+
+#[derive(Default)]
+pub struct LoadLimits {
+    pub max_nodes: Option<usize>,
+    pub max_source_bytes: Option<usize>,
+    pub max_remote_urls: Option<usize>,
+}
+
+/// A reference, or one of the imports and re-exports it goes through, names
+/// a declaration its module does not have.
+#[derive(Debug)]
+pub struct MissingDeclaration {
+    pub identifier: FuneeIdentifier,
+    pub referrer: String,
+}
+
+impl fmt::Display for MissingDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Could not find declaration for '{}' in {}, referenced from {}",
+            self.identifier.name, self.identifier.uri, self.referrer
+        )
+    }
+}
+
+impl std::error::Error for MissingDeclaration {}
+
+fn check_limit(limit: Option<usize>, value: usize, description: &str) -> Result<(), AnyError> {
+    match limit {
+        Some(limit) if value > limit => Err(generic_error(format!(
+            "Source graph exceeded the limit of {} {}",
+            limit, description
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn describe_chain(chain: &[FuneeIdentifier]) -> String {
+    chain
+        .iter()
+        .map(|identifier| format!("{} in {}", identifier.name, identifier.uri))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+fn host_fn_declaration(
+    identifier: &FuneeIdentifier,
+    async_host_functions: &HashSet<FuneeIdentifier>,
+) -> Declaration {
+    Declaration::HostFn {
+        name: identifier.name.clone(),
+        is_async: async_host_functions.contains(identifier),
+    }
+}
+
+/// Wraps the module's statements in an immediately called async function,
+/// which also allows them to await at the top level. Its imports stay
+/// resolvable from the scope, as references to them are left free.
+fn script_expression(module: Module) -> Expr {
+    let stmts = module
+        .body
+        .into_iter()
+        .filter_map(|module_item| match module_item {
+            ModuleItem::Stmt(stmt) => Some(stmt),
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export_decl)) => {
+                Some(Stmt::Decl(export_decl.decl))
+            }
+            ModuleItem::ModuleDecl(_) => None,
+        })
+        .collect();
+    Expr::Call(CallExpr {
+        span: Default::default(),
+        callee: Callee::Expr(Box::new(Expr::Paren(ParenExpr {
+            span: Default::default(),
+            expr: Box::new(Expr::Fn(FnExpr {
+                ident: None,
+                function: Function {
+                    params: vec![],
+                    decorators: vec![],
+                    span: Default::default(),
+                    body: Some(BlockStmt {
+                        span: Default::default(),
+                        stmts,
+                    }),
+                    is_generator: false,
+                    is_async: true,
+                    type_params: None,
+                    return_type: None,
+                },
+            })),
+        }))),
+        args: vec![],
+        type_args: None,
+    })
 }
 
 impl SourceGraph {
-    pub fn load(params: LoadParams) -> Self {
+    pub fn load(params: LoadParams) -> Result<Self, AnyError> {
         let globals = Globals::default();
-        let cm = Rc::new(SourceMap::with_file_loader(
-            params.file_loader,
-            FilePathMapping::empty(),
-        ));
+        let module_loader = ModuleLoader::new(params.file_loader);
+        let cm = module_loader.cm.clone();
         let unresolved_mark = GLOBALS.set(&globals, || Mark::new());
         let mut definitions_index = HashMap::new();
+        let mut loaded_uris = HashSet::new();
+        let mut source_bytes = 0;
+        let mut remote_urls = 0;
+        let mut warnings = vec![];
+        let mut resolution_trace = vec![];
+        let mut modules = HashMap::new();
+        let resolver = Resolver {
+            cm: &cm,
+            import_map: &params.import_map,
+            extensions: &params.resolve_extensions,
+        };
+        let loader = DeclarationLoader {
+            module_loader: &module_loader,
+            asset_loaders: &params.asset_loaders,
+            source_transforms: &params.source_transforms,
+            parse_options: &params.parse_options,
+            resolver: &resolver,
+            allowed_extensions: &params.allowed_extensions,
+        };
+        let scope = normalize_uri(&params.scope);
+        let expression = match params.entry_mode {
+            EntryMode::Expression => params.expression,
+            EntryMode::Script => script_expression(loader.load_script(&scope, &mut modules)?),
+        };
         let mut graph = Graph::new();
-        let root_node = graph.add_node((params.scope, Declaration::Expr(params.expression)));
+        let root_node = graph.add_node((scope, Declaration::Expr(expression)));
         let mut dfs = Dfs::new(&graph, root_node);
         while let Some(nx) = dfs.next(&graph) {
             let (t, declaration) = &mut graph[nx];
@@ -68,71 +305,126 @@ impl SourceGraph {
                     .collect(),
             };
 
-            for reference in references {
-                let declaration = if params.host_functions.contains(&reference.1) {
-                    Declaration::HostFn(
-                        params
-                            .host_functions
-                            .get(&reference.1)
-                            .unwrap()
-                            .name
-                            .clone(),
+            'references: for reference in references {
+                let (identifier, declaration) = if params.host_functions.contains(&reference.1) {
+                    (
+                        reference.1.clone(),
+                        host_fn_declaration(&reference.1, &params.async_host_functions),
                     )
                 } else {
                     let mut current_identifier = reference.1.clone();
-                    loop {
-                        let declaration = load_declaration(&cm, &current_identifier)
-                            .expect(
-                                &("Could not find declaration for ".to_owned()
-                                    + reference.1.uri.as_str()
-                                    + ":"
-                                    + reference.1.name.as_str()),
-                            )
-                            .declaration;
-
-                        if let Declaration::FuneeIdentifier(i) = declaration {
-                            if params.host_functions.contains(&i) {
-                                break Declaration::HostFn(
-                                    params.host_functions.get(&i).unwrap().name.clone(),
-                                );
-                            }
-                            let relative_path = RelativePath::new(&i.uri);
-                            let current_dir = Path::new(&current_identifier.uri)
-                                .parent()
-                                .unwrap()
-                                .to_str()
-                                .unwrap();
-                            current_identifier = FuneeIdentifier {
-                                name: i.name,
-                                uri: relative_path
-                                    .to_logical_path(&current_dir)
-                                    .to_str()
-                                    .unwrap()
-                                    .to_string(),
+                    let mut referrer = reference.1.uri.clone();
+                    let mut chain = vec![current_identifier.clone()];
+                    let resolved = loop {
+                        let declaration =
+                            match loader.load_declaration(&current_identifier, &mut modules)? {
+                                Some(module_declaration) => module_declaration.declaration,
+                                // Globals are only left unresolved when the module
+                                // doesn't declare or import the name itself.
+                                None if current_identifier == reference.1
+                                    && (BUILTIN_GLOBALS.contains(&reference.1.name.as_str())
+                                        || params.runtime_globals.contains(&reference.1.name)) =>
+                                {
+                                    continue 'references;
+                                }
+                                None if current_identifier == reference.1
+                                    && !matches!(params.on_unresolved, OnUnresolved::Error) =>
+                                {
+                                    if let OnUnresolved::Warn = params.on_unresolved {
+                                        warnings.push(format!(
+                                            "Leaving unresolved reference {} in {} as a global",
+                                            reference.1.name, reference.1.uri
+                                        ));
+                                    }
+                                    continue 'references;
+                                }
+                                None => {
+                                    return Err(MissingDeclaration {
+                                        identifier: current_identifier,
+                                        referrer,
+                                    }
+                                    .into())
+                                }
                             };
-                        } else {
-                            break declaration;
+
+                        if loaded_uris.insert(current_identifier.uri.clone()) {
+                            source_bytes += cm
+                                .get_source_file(&FileName::Real(PathBuf::from(
+                                    &current_identifier.uri,
+                                )))
+                                .map(|file| file.src.len())
+                                .unwrap_or(0);
+                            check_limit(
+                                params.limits.max_source_bytes,
+                                source_bytes,
+                                "source bytes",
+                            )?;
+                            if current_identifier.uri.starts_with("http://")
+                                || current_identifier.uri.starts_with("https://")
+                            {
+                                remote_urls += 1;
+                                check_limit(
+                                    params.limits.max_remote_urls,
+                                    remote_urls,
+                                    "remote URLs",
+                                )?;
+                            }
                         }
+
+                        let next_identifier = match declaration {
+                            Declaration::FuneeIdentifier(i) => {
+                                if params.host_functions.contains(&i) {
+                                    let declaration =
+                                        host_fn_declaration(&i, &params.async_host_functions);
+                                    chain.push(i.clone());
+                                    break (i, declaration);
+                                }
+                                FuneeIdentifier {
+                                    uri: resolver.resolve_uri(&current_identifier.uri, &i.uri),
+                                    name: i.name,
+                                }
+                            }
+                            Declaration::LocalAlias(name) => FuneeIdentifier {
+                                name,
+                                uri: current_identifier.uri.clone(),
+                            },
+                            declaration => break (current_identifier, declaration),
+                        };
+                        referrer = current_identifier.uri;
+                        current_identifier = next_identifier;
+                        if chain.contains(&current_identifier) {
+                            chain.push(current_identifier);
+                            return Err(generic_error(format!(
+                                "Re-export cycle: {}",
+                                describe_chain(&chain)
+                            )));
+                        }
+                        chain.push(current_identifier.clone());
+                    };
+                    if params.trace_resolution {
+                        resolution_trace.push(describe_chain(&chain));
                     }
+                    resolved
                 };
 
-                if !definitions_index.contains_key(&reference.1) {
-                    let node_index = graph.add_node((reference.1.uri.clone(), declaration));
-                    graph.add_edge(nx, node_index, reference.0);
-                    definitions_index.insert(reference.1, node_index);
+                if !definitions_index.contains_key(&identifier) {
+                    let node_index = graph.add_node((identifier.uri.clone(), declaration));
+                    check_limit(params.limits.max_nodes, graph.node_count(), "nodes")?;
+                    graph.add_edge(nx, node_index, (reference.0, None));
+                    definitions_index.insert(identifier, node_index);
 
                     if !dfs.discovered.is_visited(&node_index) {
                         dfs.discovered.grow(graph.node_count());
                         dfs.stack.push(node_index);
                     }
                 } else {
-                    let node_index = definitions_index.get(&reference.1).unwrap();
-                    graph.add_edge(nx, *node_index, reference.0);
+                    let node_index = definitions_index.get(&identifier).unwrap();
+                    graph.add_edge(nx, *node_index, (reference.0, None));
                 }
             }
         }
 
-        Self {
+        Ok(Self {
             graph,
             source_map: cm,
             references_mark: ReferencesMark {
@@ -140,6 +432,23 @@ impl SourceGraph {
                 globals,
             },
             root: root_node,
+            warnings,
+            resolution_trace,
+        })
+    }
+
+    /// The host functions referenced by declarations reachable from the root.
+    pub fn used_host_functions(&self) -> HashSet<FuneeIdentifier> {
+        let mut used = HashSet::new();
+        let mut dfs = Dfs::new(&self.graph, self.root);
+        while let Some(nx) = dfs.next(&self.graph) {
+            if let (uri, Declaration::HostFn { name, .. }) = &self.graph[nx] {
+                used.insert(FuneeIdentifier {
+                    name: name.clone(),
+                    uri: uri.clone(),
+                });
+            }
         }
+        used
     }
 }
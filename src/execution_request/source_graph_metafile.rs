@@ -0,0 +1,67 @@
+use super::{declaration::Declaration, source_graph::SourceGraph};
+use deno_core::serde_json::{json, Map, Value};
+use petgraph::Direction::Outgoing;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+use swc_common::FileName;
+
+impl SourceGraph {
+    pub fn metafile(&self, output_path: &str, output: &str) -> Value {
+        let mut imports: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for nx in self.graph.node_indices() {
+            let (uri, declaration) = &self.graph[nx];
+            if let Declaration::HostFn { .. } = declaration {
+                continue;
+            }
+            let module_imports = imports.entry(uri.as_str()).or_default();
+            for target in self.graph.neighbors_directed(nx, Outgoing) {
+                let (target_uri, target_declaration) = &self.graph[target];
+                if target_uri != uri && !matches!(target_declaration, Declaration::HostFn { .. }) {
+                    module_imports.insert(target_uri.as_str());
+                }
+            }
+        }
+
+        let inputs: Map<String, Value> = imports
+            .iter()
+            .map(|(uri, module_imports)| {
+                (
+                    uri.to_string(),
+                    json!({
+                        "bytes": self.source_bytes(uri),
+                        "imports": module_imports
+                            .iter()
+                            .map(|path| json!({ "path": path }))
+                            .collect::<Vec<_>>(),
+                    }),
+                )
+            })
+            .collect();
+
+        let mut outputs = Map::new();
+        outputs.insert(
+            output_path.to_string(),
+            json!({
+                "bytes": output.len(),
+                "inputs": imports
+                    .keys()
+                    .map(|uri| (uri.to_string(), json!({})))
+                    .collect::<Map<String, Value>>(),
+            }),
+        );
+
+        json!({
+            "inputs": inputs,
+            "outputs": outputs,
+        })
+    }
+
+    fn source_bytes(&self, uri: &str) -> usize {
+        self.source_map
+            .get_source_file(&FileName::Real(PathBuf::from(uri)))
+            .map(|file| file.src.len())
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,139 @@
+use super::{
+    declaration::Declaration,
+    get_references_from_declaration::get_references_from_declaration,
+    source_graph::{ReferencesMark, SourceGraph},
+};
+use deno_core::{
+    error::{generic_error, AnyError},
+    serde_json,
+};
+use petgraph::{stable_graph::NodeIndex, visit::EdgeRef, Graph};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, rc::Rc};
+use swc_common::{
+    BytePos, FileName, FilePathMapping, Globals, Mark, SourceMap, Span, SyntaxContext, GLOBALS,
+};
+use swc_ecma_visit::{VisitMut, VisitMutWith};
+
+#[derive(Serialize, Deserialize)]
+struct SerializedSourceGraph {
+    source_files: Vec<(String, String)>,
+    nodes: Vec<(String, Declaration)>,
+    edges: Vec<(usize, usize, String, Option<u32>)>,
+    root: usize,
+}
+
+impl SourceGraph {
+    /// Serializes the loaded graph, together with the sources its spans point
+    /// into, so that it can be emitted later without loading it again.
+    pub fn to_json(&self) -> Result<String, AnyError> {
+        let source_files = self
+            .source_map
+            .files()
+            .iter()
+            .map(|file| (file.name.to_string(), file.src.to_string()))
+            .collect();
+        let nodes = self
+            .graph
+            .node_indices()
+            .map(|nx| self.graph[nx].clone())
+            .collect();
+        let edges = self
+            .graph
+            .edge_references()
+            .map(|edge| {
+                let (name, site) = edge.weight();
+                (
+                    edge.source().index(),
+                    edge.target().index(),
+                    name.clone(),
+                    site.map(|site| site.0),
+                )
+            })
+            .collect();
+        Ok(serde_json::to_string(&SerializedSourceGraph {
+            source_files,
+            nodes,
+            edges,
+            root: self.root.index(),
+        })?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, AnyError> {
+        let serialized: SerializedSourceGraph = serde_json::from_str(json)?;
+
+        // Source files are registered in their original order so that every
+        // span keeps pointing at the same position.
+        let cm = Rc::new(SourceMap::new(FilePathMapping::empty()));
+        for (name, src) in serialized.source_files {
+            cm.new_source_file(FileName::Real(PathBuf::from(name)), src);
+        }
+
+        // Syntax contexts belong to the globals of the process that loaded the
+        // graph, so references are resolved again against fresh ones.
+        let globals = Globals::default();
+        let unresolved_mark = GLOBALS.set(&globals, || Mark::new());
+        let mut graph = Graph::new();
+        for (uri, mut declaration) in serialized.nodes {
+            reset_syntax_contexts(&mut declaration);
+            get_references_from_declaration(&mut declaration, (&globals, unresolved_mark));
+            graph.add_node((uri, declaration));
+        }
+        let node_index = |index: usize| {
+            if index < graph.node_count() {
+                Ok(NodeIndex::new(index))
+            } else {
+                Err(generic_error(format!(
+                    "Serialized source graph refers to node {}, but has {} nodes",
+                    index,
+                    graph.node_count()
+                )))
+            }
+        };
+        let root = node_index(serialized.root)?;
+        let edges = serialized
+            .edges
+            .into_iter()
+            .map(|(source, target, name, site)| {
+                Ok((node_index(source)?, node_index(target)?, name, site))
+            })
+            .collect::<Result<Vec<_>, AnyError>>()?;
+        for (source, target, name, site) in edges {
+            graph.add_edge(source, target, (name, site.map(BytePos)));
+        }
+
+        Ok(Self {
+            graph,
+            root,
+            source_map: cm,
+            references_mark: ReferencesMark {
+                mark: unresolved_mark,
+                globals,
+            },
+            warnings: vec![],
+            resolution_trace: vec![],
+        })
+    }
+}
+
+struct ResetSyntaxContexts;
+
+impl VisitMut for ResetSyntaxContexts {
+    fn visit_mut_span(&mut self, n: &mut Span) {
+        n.ctxt = SyntaxContext::empty();
+    }
+}
+
+fn reset_syntax_contexts(declaration: &mut Declaration) {
+    match declaration {
+        Declaration::Expr(n) | Declaration::VarInit(n, _) => {
+            n.visit_mut_with(&mut ResetSyntaxContexts)
+        }
+        Declaration::FnExpr(n) => n.visit_mut_with(&mut ResetSyntaxContexts),
+        Declaration::FnDecl(n) => n.visit_mut_with(&mut ResetSyntaxContexts),
+        Declaration::Class(n) => n.visit_mut_with(&mut ResetSyntaxContexts),
+        Declaration::FuneeIdentifier(_)
+        | Declaration::LocalAlias(_)
+        | Declaration::HostFn { .. } => {}
+    }
+}
@@ -1,26 +1,96 @@
 use super::{
-    get_inline_source_map::get_inline_source_map,
-    get_references_from_declaration::rename_references_in_declaration, source_graph::SourceGraph,
+    declaration::{Declaration, HostCall, VarInitKind},
+    get_inline_source_map::{get_inline_source_map, get_source_map},
+    get_references_from_declaration::rename_references_in_declaration,
+    hoist_string_literals::{hoist_string_literals, StringLiteralHoisting},
+    output_format::{apply_output_format, OutputFormat},
+    source_graph::SourceGraph,
 };
-use crate::emit_module::emit_module;
+use crate::emit_module::{emit_module, CodegenOptions};
 use petgraph::{
+    stable_graph::NodeIndex,
     visit::{DfsPostOrder, EdgeRef},
-    Direction::Outgoing,
+    Direction::{Incoming, Outgoing},
 };
-use std::collections::HashMap;
-use swc_ecma_ast::{Module, ModuleItem};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+use swc_common::{
+    comments::{Comment, CommentKind, Comments, SingleThreadedComments},
+    BytePos, Spanned, DUMMY_SP,
+};
+use swc_ecma_ast::{Expr, ExprStmt, Lit, Module, ModuleItem, Stmt};
+
+#[derive(Default)]
+pub struct EmitOptions {
+    pub format: OutputFormat,
+    pub hoist_string_literals: Option<StringLiteralHoisting>,
+    pub source_map: SourceMapMode,
+    pub codegen: CodegenOptions,
+    pub var_init_kind: VarInitKind,
+    /// Starts the output with a `"use strict"` directive, keeping the strict
+    /// semantics the source modules had.
+    pub strict: bool,
+    /// Precedes each declaration with a comment naming the module and binding
+    /// it came from, for reading stack traces without the source map.
+    pub annotate_declarations: bool,
+    pub host_call: HostCall,
+    /// Starts the output with `#!{shebang}`, such as `/usr/bin/env node`.
+    /// Shebangs of the source modules are never carried into the output.
+    pub shebang: Option<String>,
+}
+
+#[derive(Default)]
+pub enum SourceMapMode {
+    #[default]
+    Inline,
+    External {
+        url: String,
+    },
+    None,
+}
 
 impl SourceGraph {
-    pub fn into_js_execution_code(self) -> String {
+    pub fn to_js_execution_code(&self, options: &EmitOptions) -> String {
+        self.to_js_execution_code_with_source_map(options).0
+    }
+
+    /// Returns the execution code along with the source map JSON when
+    /// `SourceMapMode::External` is requested.
+    pub fn to_js_execution_code_with_source_map(
+        &self,
+        options: &EmitOptions,
+    ) -> (String, Option<String>) {
+        let mut buf = vec![];
+        let source_map = self
+            .write_js_execution_code(options, &mut buf)
+            .expect("writing to a Vec cannot fail");
+        (
+            String::from_utf8(buf).expect("execution code is not valid utf-8"),
+            source_map,
+        )
+    }
+
+    /// Writes the execution code to `w` as it is emitted, followed by the
+    /// source map comment. Returns the source map JSON when
+    /// `SourceMapMode::External` is requested.
+    pub fn write_js_execution_code<W: Write>(
+        &self,
+        options: &EmitOptions,
+        w: &mut W,
+    ) -> io::Result<Option<String>> {
         let mut module_items: Vec<ModuleItem> = vec![];
+        let mut entry = None;
+        let comments = SingleThreadedComments::default();
         let mut dfs = DfsPostOrder::new(&self.graph, self.root);
         while let Some(nx) = dfs.next(&self.graph) {
             let edges = self.graph.edges_directed(nx, Outgoing);
-            let to_replace: HashMap<String, String> = edges
+            let to_replace: HashMap<(String, Option<BytePos>), String> = edges
                 .into_iter()
                 .map(|e| {
                     (
-                        e.weight().into(),
+                        e.weight().clone(),
                         "declaration_".to_string() + &e.target().index().to_string(),
                     )
                 })
@@ -31,18 +101,100 @@ impl SourceGraph {
                 to_replace,
                 (&self.references_mark.globals, self.references_mark.mark),
             );
-            module_items.push(
-                declaration.into_module_item("declaration_".to_string() + &nx.index().to_string()),
-            );
+            if nx == self.root {
+                entry = match declaration {
+                    Declaration::Expr(expr) => Some(expr),
+                    _ => unreachable!(),
+                };
+            } else {
+                if options.annotate_declarations {
+                    if let Some(pos) = declaration_start(&declaration) {
+                        comments.add_leading(
+                            pos,
+                            Comment {
+                                kind: CommentKind::Line,
+                                span: DUMMY_SP,
+                                text: format!(
+                                    " sourceURL: {}#{}",
+                                    self.graph[nx].0,
+                                    self.declared_name(nx)
+                                )
+                                .into(),
+                            },
+                        );
+                    }
+                }
+                module_items.push(declaration.into_module_item(
+                    "declaration_".to_string() + &nx.index().to_string(),
+                    options.var_init_kind,
+                    &options.host_call,
+                ));
+            }
         }
-        let module = Module {
-            body: module_items,
-            shebang: None,
+        let mut module = Module {
+            body: apply_output_format(module_items, entry.unwrap(), &options.format),
+            shebang: options.shebang.as_deref().map(Into::into),
             span: Default::default(),
         };
-        let (mut srcmap, buf) = emit_module(self.source_map.clone(), module);
-        let execution_code = String::from_utf8(buf).expect("asdasd")
-            + &get_inline_source_map(&self.source_map, &mut srcmap);
-        execution_code
+        if let Some(hoisting) = &options.hoist_string_literals {
+            hoist_string_literals(&mut module, hoisting);
+        }
+        if options.strict {
+            module.body.insert(
+                0,
+                ModuleItem::Stmt(Stmt::Expr(ExprStmt {
+                    span: Default::default(),
+                    expr: Box::new(Expr::Lit(Lit::Str("use strict".into()))),
+                })),
+            );
+        }
+        let mut srcmap = emit_module(
+            self.source_map.clone(),
+            module,
+            &options.codegen,
+            Some(&comments),
+            &mut *w,
+        )?;
+        match &options.source_map {
+            SourceMapMode::Inline => {
+                w.write_all(get_inline_source_map(&self.source_map, &mut srcmap).as_bytes())?;
+                Ok(None)
+            }
+            SourceMapMode::External { url } => {
+                write!(w, "\n//# sourceMappingURL={}", url)?;
+                Ok(Some(
+                    String::from_utf8(get_source_map(&self.source_map, &mut srcmap))
+                        .expect("source map is not valid utf-8"),
+                ))
+            }
+            SourceMapMode::None => Ok(None),
+        }
     }
+
+    /// The name the declaration at `nx` was declared with, or otherwise the
+    /// name it is referenced by.
+    fn declared_name(&self, nx: NodeIndex) -> String {
+        match &self.graph[nx].1 {
+            Declaration::FnDecl(fn_decl) => fn_decl.ident.sym.to_string(),
+            Declaration::Class(class_decl) => class_decl.ident.sym.to_string(),
+            _ => self
+                .graph
+                .edges_directed(nx, Incoming)
+                .map(|e| e.weight().0.clone())
+                .min()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Where the emitted declaration begins in its original module.
+fn declaration_start(declaration: &Declaration) -> Option<BytePos> {
+    let span = match declaration {
+        Declaration::FnDecl(fn_decl) => fn_decl.function.span,
+        Declaration::FnExpr(fn_expr) => fn_expr.function.span,
+        Declaration::Class(class_decl) => class_decl.class.span,
+        Declaration::VarInit(init, _) => init.span(),
+        _ => return None,
+    };
+    Some(span.lo).filter(|pos| !pos.is_dummy())
 }
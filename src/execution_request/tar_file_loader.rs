@@ -0,0 +1,99 @@
+use crate::load_module::BinaryFileLoader;
+use deno_core::error::{generic_error, AnyError};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+use swc_common::FileLoader;
+
+const BLOCK_SIZE: usize = 512;
+
+/// Loads modules from an uncompressed tar archive held in memory. Entries are
+/// loaded from absolute paths, so `src/main.ts` in the archive is
+/// `/src/main.ts`.
+pub struct TarFileLoader {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl TarFileLoader {
+    pub fn new(archive: &[u8]) -> Result<Self, AnyError> {
+        let mut files = HashMap::new();
+        let mut offset = 0;
+        while let Some(header) = archive.get(offset..offset + BLOCK_SIZE) {
+            if header.iter().all(|byte| *byte == 0) {
+                break;
+            }
+            let name = header_field(&header[0..100]);
+            let prefix = header_field(&header[345..500]);
+            let size = usize::from_str_radix(header_field(&header[124..136]).trim(), 8)
+                .map_err(|_| generic_error(format!("Invalid size of {} in tar archive", name)))?;
+            offset += BLOCK_SIZE;
+
+            let contents = archive
+                .get(offset..offset + size)
+                .ok_or_else(|| generic_error(format!("Truncated {} in tar archive", name)))?;
+            let path = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            match header[156] {
+                b'0' | 0 => {
+                    files.insert(
+                        Path::new("/").join(path.trim_start_matches("./")),
+                        contents.to_vec(),
+                    );
+                }
+                // Directories have nothing to load.
+                b'5' => {}
+                // Links, and the extended headers that would rename or
+                // resize the next entry, can't be followed.
+                typeflag => {
+                    return Err(generic_error(format!(
+                        "Unsupported entry type '{}' of {} in tar archive",
+                        typeflag as char, path
+                    )))
+                }
+            }
+            offset += size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        }
+        Ok(Self { files })
+    }
+}
+
+fn header_field(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+impl FileLoader for TarFileLoader {
+    fn file_exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn abs_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(path.to_path_buf())
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read_bytes(path)?).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} in tar archive is not UTF-8", path.display()),
+            )
+        })
+    }
+}
+
+impl BinaryFileLoader for TarFileLoader {
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
@@ -1,9 +1,34 @@
-use crate::{execution_request::ExecutionRequest, funee_identifier::FuneeIdentifier};
+use super::{
+    declaration::Declaration,
+    get_references_from_declaration::get_references_from_declaration,
+    resolve_all,
+    source_graph::{LoadParams, SourceGraph},
+    AssetLoader, BinaryFileLoader, CodegenOptions, EmitOptions, EntryMode, HostCall, ImportMap,
+    JsxRuntime, LoadLimits, MissingDeclaration, ModuleLoader, OnUnresolved, OutputFormat,
+    ParseOptions, SourceMapMode, StringLiteralHoisting, TarFileLoader, VarInitKind,
+    DEFAULT_RESOLVE_EXTENSIONS,
+};
+use crate::{
+    execution_request::ExecutionRequest,
+    funee_identifier::FuneeIdentifier,
+    host::{op_identifier, Host, RawExtension},
+    run_js::{build_runtime, globals_shim, run_js},
+};
 use ast::{CallExpr, Callee};
-use deno_core::{error::AnyError, op};
-use std::collections::HashMap;
-use swc_common::FileLoader;
+use deno_core::{error::AnyError, op, serde_json, Extension, OpState};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use swc_common::{
+    source_map::RealFileLoader, FileLoader, FileName, Globals, Mark, SourceMap, Span, GLOBALS,
+};
 use swc_ecma_ast as ast;
+use swc_ecma_parser::{parse_file_as_expr, parse_file_as_module, Syntax};
+use swc_ecma_visit::{noop_visit_type, Visit, VisitWith};
 
 #[op]
 fn op_log(something: String) -> Result<(), AnyError> {
@@ -11,6 +36,42 @@ fn op_log(something: String) -> Result<(), AnyError> {
     Ok(())
 }
 
+#[op]
+async fn op_double(n: f64) -> Result<f64, AnyError> {
+    Ok(n * 2.0)
+}
+
+/// The entries the `record` op of a `RecordingHost` was called with, shared
+/// with the test through the op state.
+#[derive(Clone, Default)]
+struct Recorded(Arc<Mutex<Vec<String>>>);
+
+#[op]
+fn op_record(state: &mut OpState, entry: String) -> Result<(), AnyError> {
+    state.borrow::<Recorded>().0.lock().unwrap().push(entry);
+    Ok(())
+}
+
+struct Greeting(String);
+
+#[op]
+fn op_op_codes() -> Result<(), AnyError> {
+    Ok(())
+}
+
+#[op]
+fn op_greeting(state: &mut OpState) -> Result<String, AnyError> {
+    Ok(state.borrow::<Greeting>().0.clone())
+}
+
+static MARKED_DONE: AtomicBool = AtomicBool::new(false);
+
+#[op]
+fn op_mark_done(value: f64) -> Result<(), AnyError> {
+    MARKED_DONE.store(value == 42.0, Ordering::SeqCst);
+    Ok(())
+}
+
 struct MockFileLoader {
     pub files: HashMap<String, String>,
 }
@@ -28,10 +89,73 @@ impl FileLoader for MockFileLoader {
 
     fn read_file(&self, path: &std::path::Path) -> std::io::Result<String> {
         println!("reading file: {}", path.to_str().unwrap());
-        Ok(self.files.get(path.to_str().unwrap()).unwrap().clone())
+        self.files
+            .get(path.to_str().unwrap())
+            .cloned()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+}
+
+impl BinaryFileLoader for MockFileLoader {}
+
+fn call_default_export() -> ast::Expr {
+    ast::Expr::Call(CallExpr {
+        span: Default::default(),
+        callee: Callee::Expr(Box::new(ast::Expr::Ident(ast::Ident::new(
+            "default".into(),
+            Default::default(),
+        )))),
+        type_args: None,
+        args: vec![],
+    })
+}
+
+fn load_params(files: &[(&str, &str)]) -> LoadParams {
+    LoadParams {
+        scope: files[0].0.to_string(),
+        expression: call_default_export(),
+        entry_mode: Default::default(),
+        host_functions: HashSet::from([FuneeIdentifier {
+            name: "log".to_string(),
+            uri: "funee".to_string(),
+        }]),
+        async_host_functions: HashSet::new(),
+        file_loader: Box::new(MockFileLoader {
+            files: files
+                .iter()
+                .map(|(path, source)| (path.to_string(), source.to_string()))
+                .collect(),
+        }),
+        limits: Default::default(),
+        asset_loaders: HashMap::new(),
+        source_transforms: HashMap::new(),
+        parse_options: Default::default(),
+        on_unresolved: Default::default(),
+        import_map: Default::default(),
+        resolve_extensions: DEFAULT_RESOLVE_EXTENSIONS.map(String::from).to_vec(),
+        runtime_globals: HashSet::new(),
+        trace_resolution: false,
+        allowed_extensions: None,
     }
 }
 
+fn load_graph(files: &[(&str, &str)]) -> SourceGraph {
+    SourceGraph::load(load_params(files)).unwrap()
+}
+
+fn assert_parses(code: &str) {
+    let cm = SourceMap::default();
+    let fm = cm.new_source_file(FileName::Anon, code.to_string());
+    parse_file_as_module(
+        &fm,
+        Syntax::Es(Default::default()),
+        ast::EsVersion::latest(),
+        None,
+        &mut vec![],
+    )
+    .expect("emitted code should parse");
+}
+
 #[test]
 fn it_works() {
     let request = ExecutionRequest {
@@ -79,6 +203,3217 @@ fn it_works() {
                 ),
             ]),
         }),
+        ..Default::default()
+    };
+    request.execute().unwrap();
+}
+
+#[test]
+fn hoists_repeated_string_literals() {
+    let literal = "a rather long string literal that repeats";
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        import { log } from "funee";
+        export default function () {
+            log("a rather long string literal that repeats");
+            log("a rather long string literal that repeats");
+            log("a rather long string literal that repeats");
+            log("short");
+        }
+        "#,
+    )]);
+
+    let code = graph.to_js_execution_code(&EmitOptions {
+        hoist_string_literals: Some(StringLiteralHoisting {
+            min_length: 20,
+            min_occurrences: 3,
+        }),
+        ..Default::default()
+    });
+
+    assert_eq!(code.matches(literal).count(), 1);
+    assert!(code.contains("var string_literal_0 = "));
+    assert_eq!(code.matches("(string_literal_0)").count(), 3);
+    assert!(code.contains(r#""short""#));
+}
+
+#[test]
+fn metafile_lists_inputs_and_their_imports() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { log } from "funee";
+            import { renameMe } from "./another.ts";
+            export default function () {
+                renameMe();
+                log("entry");
+            }
+            "#,
+        ),
+        (
+            "/app/another.ts",
+            r#"
+            import { log } from "funee";
+            export function renameMe() {
+                log("another");
+            }
+            "#,
+        ),
+    ]);
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let metafile = graph.metafile("bundle.js", &code);
+
+    let inputs = &metafile["inputs"];
+    assert_eq!(
+        inputs["/app/entry.ts"]["imports"],
+        serde_json::json!([{ "path": "/app/another.ts" }])
+    );
+    assert_eq!(inputs["/app/another.ts"]["imports"], serde_json::json!([]));
+    assert!(inputs["/app/another.ts"]["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(
+        metafile["outputs"]["bundle.js"]["bytes"],
+        serde_json::json!(code.len())
+    );
+    assert!(metafile["outputs"]["bundle.js"]["inputs"]
+        .get("/app/another.ts")
+        .is_some());
+}
+
+#[test]
+fn captures_references_in_pattern_default_values() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { helper } from "./helper.ts";
+            export default function ([first = helper()] = [], second = helper()) {
+                const { third = helper() } = {};
+                try {
+                    throw {};
+                } catch ({ fourth = helper() }) {
+                    return [first, second, third, fourth];
+                }
+            }
+            "#,
+        ),
+        (
+            "/app/helper.ts",
+            r#"
+            export function helper() {
+                return 1;
+            }
+            "#,
+        ),
+    ]);
+
+    assert!(graph
+        .graph
+        .node_weights()
+        .any(|(uri, _)| uri == "/app/helper.ts"));
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(!code.contains("helper"));
+}
+
+#[test]
+fn emits_multiple_formats_from_one_graph() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        import { log } from "funee";
+        export default function () {
+            log("formats");
+        }
+        "#,
+    )]);
+
+    let esm = graph.to_js_execution_code(&EmitOptions {
+        format: OutputFormat::Esm,
+        ..Default::default()
+    });
+    let cjs = graph.to_js_execution_code(&EmitOptions {
+        format: OutputFormat::Cjs,
+        ..Default::default()
+    });
+    let umd = graph.to_js_execution_code(&EmitOptions {
+        format: OutputFormat::Umd {
+            global_name: "formats".to_string(),
+        },
+        ..Default::default()
+    });
+
+    assert!(esm.contains("export default declaration_"));
+    assert!(cjs.contains("module.exports = declaration_"));
+    assert!(umd.contains(r#"root["formats"] = factory()"#));
+    for code in [&esm, &cjs, &umd] {
+        assert!(code.contains(r#"("formats")"#));
+        assert_parses(code);
+    }
+}
+
+#[test]
+fn emits_source_maps_according_to_mode() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        import { log } from "funee";
+        export default function () {
+            log("source maps");
+        }
+        "#,
+    )]);
+
+    let (inline, inline_map) = graph.to_js_execution_code_with_source_map(&Default::default());
+    assert!(inline.contains("//# sourceMappingURL=data:application/json;base64,"));
+    assert!(inline_map.is_none());
+
+    let (none, none_map) = graph.to_js_execution_code_with_source_map(&EmitOptions {
+        source_map: SourceMapMode::None,
+        ..Default::default()
+    });
+    assert!(!none.contains("sourceMappingURL"));
+    assert!(none_map.is_none());
+    assert_parses(&none);
+
+    let (external, external_map) = graph.to_js_execution_code_with_source_map(&EmitOptions {
+        source_map: SourceMapMode::External {
+            url: "bundle.js.map".to_string(),
+        },
+        ..Default::default()
+    });
+    assert!(external.ends_with("\n//# sourceMappingURL=bundle.js.map"));
+    let external_map: serde_json::Value = serde_json::from_str(&external_map.unwrap()).unwrap();
+    assert_eq!(
+        external_map["sources"],
+        serde_json::json!(["/app/entry.ts"])
+    );
+}
+
+#[test]
+fn resolves_exports_of_commonjs_modules() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { greet, shout } from "./greeter.cjs";
+            export default function () {
+                greet("world");
+                shout("world");
+            }
+            "#,
+        ),
+        (
+            "/app/greeter.cjs",
+            r#"
+            const { log } = require("funee");
+            function greet(name) {
+                log("hello " + name);
+            }
+            module.exports = { greet };
+            module.exports.shout = function (name) {
+                greet(name.toUpperCase());
+            };
+            "#,
+        ),
+    ]);
+
+    assert_eq!(
+        graph
+            .graph
+            .node_weights()
+            .filter(|(uri, _)| uri == "/app/greeter.cjs")
+            .count(),
+        2
+    );
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(!code.contains("require("));
+    assert!(!code.contains("exports"));
+}
+
+#[test]
+fn drops_commonjs_exports_assigned_before_module_exports_is_replaced() {
+    let error = SourceGraph::load(load_params(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { early } from "./lib.cjs";
+            export default function () {
+                return early;
+            }
+            "#,
+        ),
+        (
+            "/app/lib.cjs",
+            r#"
+            exports.early = 1;
+            module.exports = { late: 2 };
+            "#,
+        ),
+    ]))
+    .err()
+    .unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "Could not find declaration for 'early' in /app/lib.cjs, referenced from /app/entry.ts"
+    );
+}
+
+#[test]
+fn stops_loading_when_the_node_limit_is_exceeded() {
+    let files: Vec<(String, String)> = (0..20)
+        .map(|index| {
+            (
+                format!("/app/module_{}.ts", index),
+                format!(
+                    r#"
+                    import {{ step as next }} from "./module_{}.ts";
+                    export default function () {{ step(); }}
+                    export function step() {{ next(); }}
+                    "#,
+                    index + 1
+                ),
+            )
+        })
+        .collect();
+    let files: Vec<(&str, &str)> = files
+        .iter()
+        .map(|(path, source)| (path.as_str(), source.as_str()))
+        .collect();
+
+    let result = SourceGraph::load(LoadParams {
+        limits: LoadLimits {
+            max_nodes: Some(5),
+            ..Default::default()
+        },
+        ..load_params(&files)
+    });
+
+    let error = result.err().expect("loading should fail");
+    assert_eq!(
+        error.to_string(),
+        "Source graph exceeded the limit of 5 nodes"
+    );
+}
+
+#[test]
+fn rejects_import_aliases_that_collide_with_local_declarations() {
+    let result = SourceGraph::load(load_params(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { foo as bar } from "./other.ts";
+            function bar() {}
+            export default function () {
+                bar();
+            }
+            "#,
+        ),
+        ("/app/other.ts", "export function foo() {}"),
+    ]));
+
+    let error = result.err().expect("loading should fail");
+    assert_eq!(
+        error.to_string(),
+        "Identifier 'bar' has already been declared in /app/entry.ts"
+    );
+}
+
+#[test]
+fn distinguishes_import_aliases_from_local_declarations() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { foo as bar } from "./other.ts";
+            function foo() {}
+            export default function () {
+                foo();
+                bar();
+            }
+            "#,
+        ),
+        ("/app/other.ts", "export function foo() {}"),
+    ]);
+
+    let mut foo_uris: Vec<&str> = graph
+        .graph
+        .node_weights()
+        .filter(|(_, declaration)| matches!(declaration, Declaration::FnDecl(_)))
+        .map(|(uri, _)| uri.as_str())
+        .collect();
+    foo_uris.sort();
+    assert_eq!(foo_uris, vec!["/app/entry.ts", "/app/other.ts"]);
+}
+
+#[test]
+fn allows_var_to_redeclare_a_var() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        var count = 1;
+        var count = 2;
+        var count;
+        export default function () {
+            return count;
+        }
+        "#,
+    )]);
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+
+    assert_eq!(result, serde_json::json!(2));
+}
+
+#[test]
+fn bundles_text_assets_as_default_string_exports() {
+    let graph = SourceGraph::load(LoadParams {
+        asset_loaders: HashMap::from([("css".to_string(), AssetLoader::Text)]),
+        ..load_params(&[
+            (
+                "/app/entry.ts",
+                r#"
+                import { log } from "funee";
+                import styles from "./styles.css";
+                export default function () {
+                    log(styles);
+                }
+                "#,
+            ),
+            ("/app/styles.css", "body { color: red; }"),
+        ])
+    })
+    .unwrap();
+
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains(r#"= "body { color: red; }";"#));
+}
+
+#[test]
+fn bundles_binary_assets_as_base64() {
+    let dir = std::env::temp_dir().join(format!("funee-assets-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("entry.ts"),
+        r#"
+        import logo from "./logo.png";
+        export default function () {
+            return logo;
+        }
+        "#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G', 0xff, 0x00]).unwrap();
+
+    let result = SourceGraph::load(LoadParams {
+        file_loader: Box::new(RealFileLoader),
+        asset_loaders: HashMap::from([("png".to_string(), AssetLoader::Base64)]),
+        ..load_params(&[(dir.join("entry.ts").to_str().unwrap(), "")])
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let code = result.unwrap().to_js_execution_code(&Default::default());
+    assert!(code.contains(r#"= "iVBOR/8A";"#));
+}
+
+#[test]
+fn rejects_imports_of_unconfigured_extensions() {
+    let result = SourceGraph::load(load_params(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import styles from "./styles.css";
+            export default function () {
+                return styles;
+            }
+            "#,
+        ),
+        ("/app/styles.css", "body { color: red; }"),
+    ]));
+
+    let error = result.err().expect("loading should fail");
+    assert_eq!(
+        error.to_string(),
+        r#"No loader is configured for ".css" files, cannot import /app/styles.css"#
+    );
+}
+
+struct IdentSpans {
+    name: &'static str,
+    spans: Vec<Span>,
+}
+
+impl Visit for IdentSpans {
+    noop_visit_type!();
+
+    fn visit_ident(&mut self, n: &ast::Ident) {
+        if &*n.sym == self.name {
+            self.spans.push(n.span);
+        }
+    }
+}
+
+#[test]
+fn renames_same_named_references_by_site() {
+    let mut graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        export default function () {
+            target();
+            target();
+        }
+        export function target() {}
+        "#,
+    )]);
+    let default_node = graph.graph.neighbors(graph.root).next().unwrap();
+    let target_node = graph.graph.neighbors(default_node).next().unwrap();
+    let mut sites = IdentSpans {
+        name: "target",
+        spans: vec![],
+    };
+    match &graph.graph[default_node].1 {
+        Declaration::FnExpr(fn_expr) => fn_expr.visit_with(&mut sites),
+        _ => unreachable!(),
+    }
+    assert_eq!(sites.spans.len(), 2);
+
+    let edge = graph.graph.find_edge(default_node, target_node).unwrap();
+    graph.graph.remove_edge(edge);
+    let targets: Vec<_> = sites
+        .spans
+        .iter()
+        .zip([1.0, 2.0])
+        .map(|(site, value)| {
+            let node = graph.graph.add_node((
+                "/app/entry.ts".to_string(),
+                Declaration::VarInit(ast::Expr::Lit(ast::Lit::Num(value.into())), None),
+            ));
+            graph
+                .graph
+                .add_edge(default_node, node, ("target".to_string(), Some(site.lo)));
+            node
+        })
+        .collect();
+
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(!code.contains("target"));
+    let first = code
+        .find(&format!("declaration_{}();", targets[0].index()))
+        .unwrap();
+    let second = code
+        .find(&format!("declaration_{}();", targets[1].index()))
+        .unwrap();
+    assert!(first < second);
+}
+
+#[test]
+fn resolves_renamed_re_exports_relative_to_the_re_exporting_module() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { public as exposed } from "./lib/index.ts";
+            export default function () {
+                exposed();
+            }
+            "#,
+        ),
+        (
+            "/app/lib/index.ts",
+            r#"
+            export { internal as public } from "../impl/impl.ts";
+            "#,
+        ),
+        (
+            "/app/impl/impl.ts",
+            r#"
+            import { log } from "funee";
+            export function internal() {
+                log("internal");
+            }
+            "#,
+        ),
+    ]);
+
+    assert!(graph.graph.node_weights().any(|(uri, declaration)| uri
+        == "/app/impl/impl.ts"
+        && matches!(declaration, Declaration::FnDecl(fn_decl) if &*fn_decl.ident.sym == "internal")));
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains(r#"("internal")"#));
+    assert_parses(&code);
+}
+
+#[test]
+fn reports_the_location_of_parse_errors() {
+    let error = SourceGraph::load(load_params(&[(
+        "/app/entry.ts",
+        "export default function () {}\nconst = 1;\n",
+    )]))
+    .err()
+    .unwrap();
+
+    assert!(error
+        .to_string()
+        .starts_with("Could not parse /app/entry.ts:2:"));
+}
+
+#[test]
+fn applies_source_transforms_before_parsing() {
+    let mut params = load_params(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import render from "./component.vue";
+            export default function () {
+                render();
+            }
+            "#,
+        ),
+        (
+            "/app/component.vue",
+            r#"
+            import { log } from "funee";
+            export default function () {
+                log("marker");
+            }
+            "#,
+        ),
+    ]);
+    params.source_transforms.insert(
+        "vue".to_string(),
+        Box::new(|path: &str, source: &str| -> Result<String, AnyError> {
+            assert_eq!(path, "/app/component.vue");
+            Ok(source.replace("marker", "MARKER"))
+        }),
+    );
+
+    let graph = SourceGraph::load(params).unwrap();
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains(r#"("MARKER")"#));
+    assert!(!code.contains(r#"("marker")"#));
+    let component = graph
+        .source_map
+        .get_source_file(&FileName::Real("/app/component.vue".into()))
+        .unwrap();
+    assert!(component.src.contains("MARKER"));
+}
+
+#[test]
+fn parses_decorators_without_compiling_them() {
+    let mut params = load_params(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { Service } from "./service.ts";
+            export default function () {
+                return new Service();
+            }
+            "#,
+        ),
+        (
+            "/app/service.ts",
+            r#"
+            function sealed(constructor: Function) {
+                Object.seal(constructor);
+            }
+            @sealed
+            export class Service {}
+            "#,
+        ),
+    ]);
+    params.parse_options = ParseOptions {
+        decorators: true,
+        ..Default::default()
+    };
+
+    let graph = SourceGraph::load(params).unwrap();
+    assert!(graph.graph.node_weights().any(|(uri, declaration)| uri
+        == "/app/service.ts"
+        && matches!(declaration, Declaration::FnDecl(fn_decl) if &*fn_decl.ident.sym == "sealed")));
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains("@declaration_"));
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    assert!(runtime.block_on(run_js(&code, vec![], &[])).is_err());
+}
+
+#[test]
+fn compiles_jsx_with_the_configured_pragma() {
+    let mut params = load_params(&[
+        (
+            "/app/entry.tsx",
+            r#"
+            import { Greeting } from "./greeting.tsx";
+            export default function () {
+                return <><Greeting name="world" /></>;
+            }
+            "#,
+        ),
+        (
+            "/app/greeting.tsx",
+            r#"
+            export function Greeting(props: { name: string }) {
+                return <div className="greeting">Hello {props.name}</div>;
+            }
+            "#,
+        ),
+    ]);
+    params.parse_options.jsx_runtime = JsxRuntime::Classic {
+        pragma: "h".to_string(),
+        pragma_frag: "Fragment".to_string(),
+    };
+    params.runtime_globals = HashSet::from(["h".to_string(), "Fragment".to_string()]);
+
+    let graph = SourceGraph::load(params).unwrap();
+    assert!(graph.graph.node_weights().any(|(uri, declaration)| uri
+        == "/app/greeting.tsx"
+        && matches!(declaration, Declaration::FnDecl(fn_decl) if &*fn_decl.ident.sym == "Greeting")));
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains("h(Fragment, null"));
+    assert!(code.contains("className"));
+    assert!(!code.contains("<div"));
+    assert_parses(&code);
+}
+
+#[test]
+fn compiles_jsx_with_the_automatic_runtime_through_an_import_map() {
+    let mut params = load_params(&[
+        (
+            "/app/entry.tsx",
+            r#"
+            export default function () {
+                return <div className="greeting">hello</div>;
+            }
+            "#,
+        ),
+        (
+            "/vendor/jsx-runtime.ts",
+            r#"
+            export function jsx(type: string, props: { className: string; children: string }) {
+                return type + "." + props.className + ": " + props.children;
+            }
+            "#,
+        ),
+    ]);
+    params.parse_options.jsx_runtime = JsxRuntime::Automatic {
+        import_source: "react".to_string(),
+    };
+    params.import_map = ImportMap {
+        imports: HashMap::from([(
+            "react/jsx-runtime".to_string(),
+            "/vendor/jsx-runtime.ts".to_string(),
+        )]),
+        ..Default::default()
+    };
+
+    let code = SourceGraph::load(params)
+        .unwrap()
+        .to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+    assert_eq!(result, serde_json::json!("div.greeting: hello"));
+}
+
+#[test]
+fn resolves_relative_imports_inside_remote_modules() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { format } from "https://cdn.example.com/lib/index.ts";
+            export default function () {
+                format();
+            }
+            "#,
+        ),
+        (
+            "https://cdn.example.com/lib/index.ts",
+            r#"
+            export { format } from "./helpers/format.ts";
+            "#,
+        ),
+        (
+            "https://cdn.example.com/lib/helpers/format.ts",
+            r#"
+            import { log } from "/lib/core.ts";
+            export function format() {
+                log("format");
+            }
+            "#,
+        ),
+        (
+            "https://cdn.example.com/lib/core.ts",
+            r#"
+            export { log } from "funee";
+            "#,
+        ),
+    ]);
+
+    assert!(graph.graph.node_weights().any(|(uri, declaration)| uri
+        == "https://cdn.example.com/lib/helpers/format.ts"
+        && matches!(declaration, Declaration::FnDecl(fn_decl) if &*fn_decl.ident.sym == "format")));
+    assert!(graph
+        .graph
+        .node_weights()
+        .any(|(_, declaration)| matches!(declaration, Declaration::HostFn { .. })));
+}
+
+#[test]
+fn promotes_a_named_binding_to_the_default_export() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { helper } from "./helper.ts";
+            function impl() {
+                helper();
+            }
+            export { impl as default };
+            "#,
+        ),
+        (
+            "/app/helper.ts",
+            r#"
+            import { log } from "funee";
+            function helper() {
+                log("helper");
+            }
+            export { helper as default, helper };
+            "#,
+        ),
+    ]);
+
+    assert!(graph.graph.node_weights().any(|(uri, declaration)| uri
+        == "/app/entry.ts"
+        && matches!(declaration, Declaration::FnDecl(fn_decl) if &*fn_decl.ident.sym == "impl")));
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains(r#"("helper")"#));
+    assert_parses(&code);
+}
+
+#[test]
+fn strips_the_byte_order_mark_and_shebang_of_loaded_modules() {
+    let graph = load_graph(&[(
+        "/app/cli.ts",
+        "\u{feff}#!/usr/bin/env node
+        import { log } from \"funee\";
+        export default function () {
+            log(\"cli\");
+        }
+        ",
+    )]);
+
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(!code.starts_with("#!"));
+    assert!(!code.contains('\u{feff}'));
+    assert_parses(&code);
+
+    let code = graph.to_js_execution_code(&EmitOptions {
+        shebang: Some("/usr/bin/env node".to_string()),
+        ..Default::default()
+    });
+    assert!(code.starts_with("#!/usr/bin/env node"));
+    assert_parses(&code);
+}
+
+#[test]
+fn registers_ops_the_bundle_does_not_import_unless_pruning() {
+    let request = |prune_unused_ops| ExecutionRequest {
+        expression: call_default_export(),
+        scope: "/app/entry.ts".to_string(),
+        host_functions: HashMap::from([(
+            FuneeIdentifier {
+                name: "double".to_string(),
+                uri: "funee".to_string(),
+            },
+            op_double::decl(),
+        )]),
+        file_loader: Box::new(MockFileLoader {
+            files: HashMap::from([(
+                "/app/entry.ts".to_string(),
+                r#"
+                export default function () {
+                    return Deno.core.opAsync("op_double", 21);
+                }
+                "#
+                .to_string(),
+            )]),
+        }),
+        prune_unused_ops,
+        ..Default::default()
     };
-    assert_eq!(request.execute().unwrap(), ());
+
+    assert_eq!(request(false).execute().unwrap().as_f64(), Some(42.0));
+    assert!(request(true).execute().is_err());
+}
+
+#[test]
+fn reports_only_the_host_functions_the_bundle_uses() {
+    let mut params = load_params(&[(
+        "/app/entry.ts",
+        r#"
+        import { log } from "funee";
+        export default function () {
+            log("used");
+        }
+        "#,
+    )]);
+    params.host_functions.insert(FuneeIdentifier {
+        name: "exit".to_string(),
+        uri: "funee".to_string(),
+    });
+
+    let graph = SourceGraph::load(params).unwrap();
+
+    assert_eq!(
+        graph.used_host_functions(),
+        HashSet::from([FuneeIdentifier {
+            name: "log".to_string(),
+            uri: "funee".to_string(),
+        }])
+    );
+}
+
+#[test]
+fn escapes_non_ascii_characters_when_ascii_only_is_set() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        import { log } from "funee";
+        export default function () {
+            log("héllo wörld");
+        }
+        "#,
+    )]);
+
+    let default = graph.to_js_execution_code(&EmitOptions {
+        source_map: SourceMapMode::None,
+        ..Default::default()
+    });
+    let ascii_only = graph.to_js_execution_code(&EmitOptions {
+        source_map: SourceMapMode::None,
+        codegen: CodegenOptions {
+            ascii_only: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    assert!(default.contains("héllo wörld"));
+    assert!(ascii_only.is_ascii());
+    assert_parses(&ascii_only);
+}
+
+#[test]
+fn captures_references_inside_jsx() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.tsx",
+            r#"
+            import { log } from "funee";
+            import { Button } from "./button.tsx";
+            export default function () {
+                const handler = () => log("clicked");
+                return <div><Button onClick={handler} /></div>;
+            }
+            "#,
+        ),
+        (
+            "/app/button.tsx",
+            r#"
+            export function Button(props) {
+                return props.onClick();
+            }
+            "#,
+        ),
+    ]);
+
+    assert!(graph.graph.node_weights().any(|(uri, declaration)| uri
+        == "/app/button.tsx"
+        && matches!(declaration, Declaration::FnDecl(fn_decl) if &*fn_decl.ident.sym == "Button")));
+    assert_eq!(graph.graph.node_count(), 4);
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains("onClick={handler}"));
+}
+
+#[test]
+fn round_trips_a_loaded_graph_through_json() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { log } from "funee";
+            import { renameMe } from "./another.ts";
+            export default function () {
+                renameMe();
+                log("entry");
+            }
+            "#,
+        ),
+        (
+            "/app/another.ts",
+            r#"
+            import { log } from "funee";
+            export function renameMe() {
+                log("another");
+            }
+            "#,
+        ),
+    ]);
+
+    let reloaded = SourceGraph::from_json(&graph.to_json().unwrap()).unwrap();
+
+    assert_eq!(
+        reloaded.to_js_execution_code(&Default::default()),
+        graph.to_js_execution_code(&Default::default())
+    );
+}
+
+#[test]
+fn executes_a_graph_reloaded_from_json() {
+    let files = HashMap::from([(
+        "/app/entry.ts".to_string(),
+        r#"
+        export default function () {
+            return 42;
+        }
+        "#
+        .to_string(),
+    )]);
+    let json = ExecutionRequest::builder()
+        .entry_file("/app/entry.ts")
+        .file_loader(Box::new(MockFileLoader { files }))
+        .build()
+        .unwrap()
+        .load_source_graph()
+        .unwrap()
+        .to_json()
+        .unwrap();
+
+    let result = ExecutionRequest::default()
+        .execute_source_graph(SourceGraph::from_json(&json).unwrap())
+        .unwrap();
+
+    assert_eq!(result, serde_json::json!(42));
+}
+
+#[test]
+fn rejects_serialized_graphs_with_out_of_range_nodes() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        export default function () {}
+        "#,
+    )]);
+    let mut json: serde_json::Value = serde_json::from_str(&graph.to_json().unwrap()).unwrap();
+    json["edges"] = serde_json::json!([[0, 7, "missing", null]]);
+
+    let error = SourceGraph::from_json(&json.to_string()).err().unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "Serialized source graph refers to node 7, but has 2 nodes"
+    );
+}
+
+#[test]
+fn captures_operands_of_typescript_expression_wrappers() {
+    let cm = SourceMap::default();
+    let fm = cm.new_source_file(
+        FileName::Anon,
+        "[(foo as Bar)(), bar!, <Baz>baz, qux as const]".to_string(),
+    );
+    let expression = parse_file_as_expr(
+        &fm,
+        Syntax::Typescript(Default::default()),
+        ast::EsVersion::latest(),
+        None,
+        &mut vec![],
+    )
+    .unwrap();
+    let globals = Globals::default();
+    let unresolved_mark = GLOBALS.set(&globals, || Mark::new());
+
+    let references = get_references_from_declaration(
+        &mut Declaration::Expr(*expression),
+        (&globals, unresolved_mark),
+    );
+
+    assert_eq!(
+        references,
+        HashSet::from(["foo", "bar", "baz", "qux"].map(String::from))
+    );
+}
+
+#[test]
+fn runs_shims_before_the_bundle() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let result = runtime.block_on(run_js(
+        r#"if (globalThis.shimmed !== "yes") { throw new Error("shim did not run"); }"#,
+        vec![],
+        &[r#"globalThis.shimmed = "yes";"#.to_string()],
+    ));
+
+    assert!(result.is_ok());
+}
+
+const BARREL_FILES: [(&str, &str); 3] = [
+    (
+        "/app/lib/index.ts",
+        r#"
+        export * from "./alpha.ts";
+        export * from "./beta.ts";
+        "#,
+    ),
+    (
+        "/app/lib/alpha.ts",
+        r#"
+        import { log } from "funee";
+        export function alpha() {
+            log("alpha");
+        }
+        export function shared() {}
+        "#,
+    ),
+    (
+        "/app/lib/beta.ts",
+        r#"
+        import { log } from "funee";
+        export function beta() {
+            log("beta");
+        }
+        export function shared() {}
+        "#,
+    ),
+];
+
+#[test]
+fn follows_star_re_exports_of_barrel_modules() {
+    let mut files = vec![(
+        "/app/entry.ts",
+        r#"
+        import { alpha, beta } from "./lib/index.ts";
+        export default function () {
+            alpha();
+            beta();
+        }
+        "#,
+    )];
+    files.extend(BARREL_FILES);
+
+    let code = load_graph(&files).to_js_execution_code(&Default::default());
+
+    assert!(code.contains(r#"("alpha")"#));
+    assert!(code.contains(r#"("beta")"#));
+}
+
+#[test]
+fn rejects_names_exported_by_more_than_one_star_re_export() {
+    let mut files = vec![(
+        "/app/entry.ts",
+        r#"
+        import { shared } from "./lib/index.ts";
+        export default function () {
+            shared();
+        }
+        "#,
+    )];
+    files.extend(BARREL_FILES);
+
+    let error = SourceGraph::load(load_params(&files)).err().unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "Conflicting star exports for name 'shared' in /app/lib/index.ts"
+    );
+}
+
+#[test]
+fn follows_star_re_exports_of_barrels_that_export_each_other() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { fromA, fromB } from "./a.ts";
+            export default function () {
+                fromA();
+                fromB();
+            }
+            "#,
+        ),
+        (
+            "/app/a.ts",
+            r#"
+            export * from "./b.ts";
+            export function fromA() {}
+            "#,
+        ),
+        (
+            "/app/b.ts",
+            r#"
+            export * from "./a.ts";
+            export function fromB() {}
+            "#,
+        ),
+    ]);
+
+    assert_eq!(graph.graph.node_count(), 4);
+}
+
+#[test]
+fn follows_star_re_exports_that_reach_a_declaration_along_two_paths() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { shared } from "./index.ts";
+            export default function () {
+                shared();
+            }
+            "#,
+        ),
+        (
+            "/app/index.ts",
+            r#"
+            export * from "./left.ts";
+            export * from "./right.ts";
+            "#,
+        ),
+        ("/app/left.ts", r#"export * from "./shared.ts";"#),
+        ("/app/right.ts", r#"export { shared } from "./shared.ts";"#),
+        ("/app/shared.ts", "export function shared() {}"),
+    ]);
+
+    assert!(graph
+        .graph
+        .node_weights()
+        .any(|(uri, declaration)| uri == "/app/shared.ts"
+            && matches!(declaration, Declaration::FnDecl(_))));
+}
+
+#[test]
+fn resolves_a_default_export_of_a_local_through_a_re_export() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            export { default } from "./lib/index.ts";
+            "#,
+        ),
+        (
+            "/app/lib/index.ts",
+            r#"
+            export { default } from "./greet.ts";
+            "#,
+        ),
+        (
+            "/app/lib/greet.ts",
+            r#"
+            import { log } from "funee";
+            const greet = () => log("greeted");
+            export default greet;
+            "#,
+        ),
+    ]);
+
+    assert!(graph
+        .graph
+        .node_weights()
+        .any(|(uri, declaration)| uri == "/app/lib/greet.ts"
+            && matches!(declaration, Declaration::VarInit(ast::Expr::Arrow(_), _))));
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains(r#"("greeted")"#));
+    assert_parses(&code);
+}
+
+#[test]
+fn declares_top_level_bindings_without_an_initializer() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        let counter;
+        export default function () {
+            return counter;
+        }
+        "#,
+    )]);
+
+    assert!(graph.graph.node_weights().any(|(_, declaration)| matches!(
+        declaration,
+        Declaration::VarInit(ast::Expr::Ident(ident), Some(ast::VarDeclKind::Let))
+            if &*ident.sym == "undefined"
+    )));
+    assert_parses(&graph.to_js_execution_code(&Default::default()));
+}
+
+#[test]
+fn rejects_destructured_top_level_bindings_when_used() {
+    let error = SourceGraph::load(load_params(&[(
+        "/app/entry.ts",
+        r#"
+        const { a, b } = { a: 1, b: 2 };
+        export default function () {
+            return a + b;
+        }
+        "#,
+    )]))
+    .err()
+    .unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "Cannot use 'a' from /app/entry.ts: destructuring is not supported in top-level bindings"
+    );
+}
+
+#[test]
+fn ignores_unused_destructured_top_level_bindings() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { helper } from "./lib.ts";
+            export default function () {
+                return helper();
+            }
+            "#,
+        ),
+        (
+            "/app/lib.ts",
+            r#"
+            const [first, second] = [1, 2];
+            export function helper() {
+                return "helped";
+            }
+            "#,
+        ),
+    ]);
+
+    assert!(graph
+        .to_js_execution_code(&Default::default())
+        .contains(r#""helped""#));
+}
+
+#[test]
+fn rejects_bindings_assigned_by_top_level_statements() {
+    let error = SourceGraph::load(load_params(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { Color } from "./color.ts";
+            export default function () {
+                return Color.Red;
+            }
+            "#,
+        ),
+        (
+            "/app/color.ts",
+            r#"
+            export enum Color {
+                Red,
+                Green,
+            }
+            "#,
+        ),
+    ]))
+    .err()
+    .unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "Cannot use 'Color' from /app/color.ts: it is assigned by a top-level statement"
+    );
+}
+
+#[test]
+fn handles_unresolved_references_according_to_policy() {
+    let files = [(
+        "/app/entry.ts",
+        r#"
+        export default function () {
+            providedByRuntime();
+        }
+        "#,
+    )];
+
+    let error = SourceGraph::load(load_params(&files)).err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Could not find declaration for 'providedByRuntime' in /app/entry.ts, referenced from /app/entry.ts"
+    );
+
+    for (on_unresolved, expected_warnings) in [
+        (
+            OnUnresolved::Warn,
+            vec!["Leaving unresolved reference providedByRuntime in /app/entry.ts as a global"],
+        ),
+        (OnUnresolved::Silent, vec![]),
+    ] {
+        let graph = SourceGraph::load(LoadParams {
+            on_unresolved,
+            ..load_params(&files)
+        })
+        .unwrap();
+        assert_eq!(graph.warnings, expected_warnings);
+        let code = graph.to_js_execution_code(&Default::default());
+        assert!(code.contains("providedByRuntime()"));
+    }
+}
+
+#[test]
+fn emits_variable_initializers_as_const_by_default() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        export default () => "constant";
+        "#,
+    )]);
+
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.starts_with("const declaration_"));
+
+    let code = graph.to_js_execution_code(&EmitOptions {
+        var_init_kind: VarInitKind::Var,
+        ..Default::default()
+    });
+    assert!(code.starts_with("var declaration_"));
+}
+
+#[test]
+fn bundles_class_hierarchies_across_files() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { Base } from "./base.ts";
+            function label() {
+                return "child";
+            }
+            export class Child extends Base {
+                static tag = label();
+            }
+            export default function () {
+                new Child().greet(Child.tag);
+            }
+            "#,
+        ),
+        (
+            "/app/base.ts",
+            r#"
+            import { log } from "funee";
+            export default class {}
+            export class Base {
+                greet(name) {
+                    log(name);
+                }
+            }
+            "#,
+        ),
+    ]);
+
+    let classes: Vec<_> = graph
+        .graph
+        .node_weights()
+        .filter(|(_, declaration)| matches!(declaration, Declaration::Class(_)))
+        .map(|(uri, _)| uri.as_str())
+        .collect();
+    assert_eq!(classes, vec!["/app/entry.ts", "/app/base.ts"]);
+    let code = graph.to_js_execution_code(&Default::default());
+    let base = code.find("class declaration_").unwrap();
+    assert!(code[base..].contains(" extends declaration_"));
+    assert_parses(&code);
+}
+
+#[test]
+fn embeds_sources_content_in_the_inline_source_map() {
+    let source = r#"
+        import { log } from "funee";
+        export default function () {
+            log("embedded");
+        }
+        "#;
+    let graph = load_graph(&[("/app/entry.ts", source)]);
+
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let (_, encoded) = code
+        .split_once("//# sourceMappingURL=data:application/json;base64,")
+        .unwrap();
+    let map: serde_json::Value = serde_json::from_slice(&base64::decode(encoded).unwrap()).unwrap();
+    assert_eq!(map["sources"], serde_json::json!(["/app/entry.ts"]));
+    assert_eq!(map["sourcesContent"], serde_json::json!([source]));
+}
+
+#[test]
+fn does_not_lower_parameters_shadowing_host_functions() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { helper } from "./helper.ts";
+            function run(log) {
+                log("param");
+            }
+            export default function () {
+                run((message) => message);
+                helper();
+            }
+            "#,
+        ),
+        (
+            "/app/helper.ts",
+            r#"
+            import { log } from "funee";
+            export function helper() {
+                log("host");
+            }
+            "#,
+        ),
+    ]);
+
+    assert_eq!(graph.used_host_functions().len(), 1);
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains(r#"log("param")"#));
+    assert!(!code.contains(r#"log("host")"#));
+    assert!(code.contains(r#"("host")"#));
+}
+
+#[test]
+fn reports_missing_imports_with_the_referring_module() {
+    let error = SourceGraph::load(load_params(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { mising } from "./lib.ts";
+            export default function () {
+                mising();
+            }
+            "#,
+        ),
+        (
+            "/app/lib.ts",
+            r#"
+            export function missing() {}
+            "#,
+        ),
+    ]))
+    .err()
+    .unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "Could not find declaration for 'mising' in /app/lib.ts, referenced from /app/entry.ts"
+    );
+    let missing = error.downcast_ref::<MissingDeclaration>().unwrap();
+    assert_eq!(
+        missing.identifier,
+        FuneeIdentifier {
+            name: "mising".to_string(),
+            uri: "/app/lib.ts".to_string(),
+        }
+    );
+    assert_eq!(missing.referrer, "/app/entry.ts");
+}
+
+struct CountingFileLoader {
+    inner: MockFileLoader,
+    reads: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl FileLoader for CountingFileLoader {
+    fn file_exists(&self, path: &std::path::Path) -> bool {
+        self.inner.file_exists(path)
+    }
+
+    fn abs_path(&self, path: &std::path::Path) -> Option<std::path::PathBuf> {
+        self.inner.abs_path(path)
+    }
+
+    fn read_file(&self, path: &std::path::Path) -> std::io::Result<String> {
+        *self
+            .reads
+            .lock()
+            .unwrap()
+            .entry(path.to_str().unwrap().to_string())
+            .or_default() += 1;
+        self.inner.read_file(path)
+    }
+}
+
+impl BinaryFileLoader for CountingFileLoader {}
+
+#[test]
+fn parses_each_module_once_per_load() {
+    let files = [
+        (
+            "/app/entry.ts",
+            r#"
+            import { one, two, three } from "./lib.ts";
+            function local() {
+                one();
+            }
+            export default function () {
+                local();
+                two();
+                three();
+            }
+            "#,
+        ),
+        (
+            "/app/lib.ts",
+            r#"
+            import { log } from "funee";
+            export function one() {
+                log("one");
+            }
+            export function two() {
+                log("two");
+            }
+            export function three() {
+                log("three");
+            }
+            "#,
+        ),
+    ];
+    let reads = Arc::new(Mutex::new(HashMap::new()));
+    let params = load_params(&files);
+
+    SourceGraph::load(LoadParams {
+        file_loader: Box::new(CountingFileLoader {
+            inner: MockFileLoader {
+                files: files
+                    .iter()
+                    .map(|(path, source)| (path.to_string(), source.to_string()))
+                    .collect(),
+            },
+            reads: reads.clone(),
+        }),
+        ..params
+    })
+    .unwrap();
+
+    assert_eq!(
+        *reads.lock().unwrap(),
+        HashMap::from([
+            ("/app/entry.ts".to_string(), 1),
+            ("/app/lib.ts".to_string(), 1),
+        ])
+    );
+}
+
+#[test]
+fn prefers_the_most_specific_import_map_scope() {
+    let import_map: ImportMap = serde_json::from_value(serde_json::json!({
+        "imports": { "greet": "/vendor/greet_v1.ts" },
+        "scopes": {
+            "/app/": { "other": "/vendor/other.ts" },
+            "/app/pkg/": { "greet": "/vendor/greet_v2.ts" }
+        }
+    }))
+    .unwrap();
+    let greet = |version: &str| {
+        format!(
+            r#"
+            import {{ log }} from "funee";
+            export function greet() {{
+                log("{}");
+            }}
+            "#,
+            version
+        )
+    };
+    let (v1, v2) = (greet("v1"), greet("v2"));
+
+    let graph = SourceGraph::load(LoadParams {
+        import_map,
+        ..load_params(&[
+            (
+                "/app/entry.ts",
+                r#"
+                import { greet } from "greet";
+                import { run } from "./pkg/index.ts";
+                export default function () {
+                    greet();
+                    run();
+                }
+                "#,
+            ),
+            (
+                "/app/pkg/index.ts",
+                r#"
+                import { greet } from "greet";
+                export function run() {
+                    greet();
+                }
+                "#,
+            ),
+            ("/vendor/greet_v1.ts", v1.as_str()),
+            ("/vendor/greet_v2.ts", v2.as_str()),
+        ])
+    })
+    .unwrap();
+
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains(r#"("v1")"#));
+    assert!(code.contains(r#"("v2")"#));
+}
+
+#[test]
+fn captures_references_in_accessors_and_computed_keys() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { helper, KEY } from "./lib.ts";
+            export default function () {
+                const object = {
+                    get value() {
+                        return helper();
+                    },
+                    set value(next) {
+                        helper(next);
+                    },
+                    [KEY]: 1,
+                };
+                return object.value;
+            }
+            "#,
+        ),
+        (
+            "/app/lib.ts",
+            r#"
+            import { log } from "funee";
+            export const KEY = "key";
+            export function helper() {
+                log("called");
+            }
+            "#,
+        ),
+    ]);
+
+    let lib_declarations = graph
+        .graph
+        .node_weights()
+        .filter(|(uri, _)| uri == "/app/lib.ts")
+        .count();
+    assert_eq!(lib_declarations, 2);
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(!code.contains("helper"));
+    assert!(!code.contains("KEY"));
+}
+
+#[test]
+fn resolves_many_identifiers_with_one_parse_per_module() {
+    let reads = Arc::new(Mutex::new(HashMap::new()));
+    let module_loader = ModuleLoader::new(Box::new(CountingFileLoader {
+        inner: MockFileLoader {
+            files: HashMap::from([
+                (
+                    "/app/a.ts".to_string(),
+                    "export function one() {}\nexport function two() {}".to_string(),
+                ),
+                (
+                    "/app/b.ts".to_string(),
+                    "export function three() {}".to_string(),
+                ),
+            ]),
+        },
+        reads: reads.clone(),
+    }));
+    let identifier = |name: &str, uri: &str| FuneeIdentifier {
+        name: name.to_string(),
+        uri: uri.to_string(),
+    };
+    let identifiers = [
+        identifier("one", "/app/a.ts"),
+        identifier("three", "/app/b.ts"),
+        identifier("two", "/app/a.ts"),
+    ];
+
+    let declarations = resolve_all(&module_loader, &identifiers).unwrap();
+
+    for identifier in &identifiers {
+        match &declarations[identifier] {
+            Declaration::FnDecl(fn_decl) => assert_eq!(&*fn_decl.ident.sym, identifier.name),
+            _ => unreachable!(),
+        }
+    }
+    assert!(reads.lock().unwrap().values().all(|reads| *reads == 1));
+    assert_eq!(reads.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn exposes_runtime_globals_to_the_bundle() {
+    let globals = HashMap::from([(
+        "config".to_string(),
+        serde_json::json!({ "requestId": "abc" }),
+    )]);
+    let graph = SourceGraph::load(LoadParams {
+        runtime_globals: globals.keys().cloned().collect(),
+        ..load_params(&[(
+            "/app/entry.ts",
+            r#"
+            export default function () {
+                if (config.requestId !== "abc") {
+                    throw new Error("config was not injected");
+                }
+            }
+            "#,
+        )])
+    })
+    .unwrap();
+    let code = graph.to_js_execution_code(&Default::default());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    let result = runtime.block_on(run_js(&code, vec![], &[globals_shim(&globals)]));
+
+    assert!(result.is_ok());
+}
+
+#[test]
+fn leaves_builtin_globals_unresolved() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        export default async function () {
+            const keys = Object.keys(JSON.parse("{}"));
+            await Promise.resolve(Math.max(keys.length, 0));
+            throw new Error(String(globalThis === undefined));
+        }
+        "#,
+    )]);
+
+    assert_eq!(graph.graph.node_count(), 2);
+}
+
+#[test]
+fn resolves_builtin_global_names_the_module_declares() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { Map } from "./immutable.ts";
+            function isNaN() {
+                return "local";
+            }
+            export default function () {
+                return isNaN() + " " + new Map().kind;
+            }
+            "#,
+        ),
+        (
+            "/app/immutable.ts",
+            r#"
+            export class Map {
+                kind = "immutable";
+            }
+            "#,
+        ),
+    ]);
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+
+    assert_eq!(result, serde_json::json!("local immutable"));
+}
+
+#[test]
+fn resolves_bare_specifiers_through_the_import_map() {
+    let graph = SourceGraph::load(LoadParams {
+        import_map: ImportMap {
+            imports: HashMap::from([
+                ("zod".to_string(), "/vendor/zod/mod.ts".to_string()),
+                ("std/".to_string(), "/vendor/std/".to_string()),
+            ]),
+            ..Default::default()
+        },
+        ..load_params(&[
+            (
+                "/app/entry.ts",
+                r#"
+                import { z } from "zod";
+                import { join } from "std/path/join.ts";
+                export default function () {
+                    z();
+                    join();
+                }
+                "#,
+            ),
+            (
+                "/vendor/zod/mod.ts",
+                r#"
+                export function z() {}
+                "#,
+            ),
+            (
+                "/vendor/std/path/join.ts",
+                r#"
+                export function join() {}
+                "#,
+            ),
+        ])
+    })
+    .unwrap();
+
+    let mut uris: Vec<_> = graph
+        .graph
+        .node_weights()
+        .filter(|(_, declaration)| matches!(declaration, Declaration::FnDecl(_)))
+        .map(|(uri, _)| uri.as_str())
+        .collect();
+    uris.sort();
+    assert_eq!(uris, vec!["/vendor/std/path/join.ts", "/vendor/zod/mod.ts"]);
+}
+
+#[test]
+fn infers_extensions_and_directory_indexes() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { format } from "./utils";
+            import { widget } from "./widgets";
+            export default function () {
+                format();
+                widget();
+            }
+            "#,
+        ),
+        (
+            "/app/utils.ts",
+            r#"
+            export function format() {}
+            "#,
+        ),
+        (
+            "/app/widgets/index.ts",
+            r#"
+            export { widget } from "./widget";
+            "#,
+        ),
+        (
+            "/app/widgets/widget.tsx",
+            r#"
+            export function widget() {}
+            "#,
+        ),
+    ]);
+
+    let mut uris: Vec<_> = graph
+        .graph
+        .node_weights()
+        .filter(|(_, declaration)| matches!(declaration, Declaration::FnDecl(_)))
+        .map(|(uri, _)| uri.as_str())
+        .collect();
+    uris.sort();
+    assert_eq!(uris, vec!["/app/utils.ts", "/app/widgets/widget.tsx"]);
+}
+
+#[test]
+fn infers_extensions_of_files_named_like_a_directory() {
+    let dir = std::env::temp_dir().join(format!("funee-resolve-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("utils")).unwrap();
+    std::fs::write(
+        dir.join("entry.ts"),
+        r#"
+        import { format } from "./utils";
+        export default function () {
+            format();
+        }
+        "#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("utils.ts"), "export function format() {}").unwrap();
+
+    let result = SourceGraph::load(LoadParams {
+        file_loader: Box::new(RealFileLoader),
+        ..load_params(&[(dir.join("entry.ts").to_str().unwrap(), "")])
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let graph = result.unwrap();
+    let utils = dir.join("utils.ts");
+    assert!(graph
+        .graph
+        .node_weights()
+        .any(|(uri, _)| uri == utils.to_str().unwrap()));
+}
+
+#[test]
+fn infers_extensions_without_asking_the_host_filesystem() {
+    // The loader reports the `/app/utils` directory as existing, as loaders
+    // of archives do, while no such path exists on the host.
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { format } from "./utils";
+            export default function () {
+                format();
+            }
+            "#,
+        ),
+        ("/app/utils", ""),
+        (
+            "/app/utils.ts",
+            r#"
+            export function format() {}
+            "#,
+        ),
+    ]);
+
+    assert!(graph
+        .graph
+        .node_weights()
+        .any(|(uri, _)| uri == "/app/utils.ts"));
+}
+
+#[test]
+fn only_infers_the_configured_extensions() {
+    let error = SourceGraph::load(LoadParams {
+        resolve_extensions: vec!["js".to_string()],
+        ..load_params(&[
+            (
+                "/app/entry.ts",
+                r#"
+                import { format } from "./utils";
+                export default function () {
+                    format();
+                }
+                "#,
+            ),
+            (
+                "/app/utils.ts",
+                r#"
+                export function format() {}
+                "#,
+            ),
+        ])
+    })
+    .err()
+    .unwrap();
+
+    assert!(error.to_string().starts_with("Could not load /app/utils:"));
+}
+
+#[test]
+fn rejects_value_imports_from_type_declaration_modules() {
+    let error = SourceGraph::load(load_params(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { parse } from "./types/parser.d.ts";
+            export default function () {
+                parse();
+            }
+            "#,
+        ),
+        (
+            "/app/types/parser.d.ts",
+            r#"
+            export declare function parse(): void;
+            "#,
+        ),
+    ]))
+    .err()
+    .unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "/app/types/parser.d.ts is a type-only module, it has no runtime export 'parse'"
+    );
+}
+
+#[test]
+fn skips_star_re_exports_of_type_declaration_modules() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { parse } from "./index.ts";
+            export default function () {
+                parse();
+            }
+            "#,
+        ),
+        (
+            "/app/index.ts",
+            r#"
+            export * from "./types.d.ts";
+            export * from "./parser.ts";
+            "#,
+        ),
+        ("/app/types.d.ts", "export declare type Options = {};"),
+        ("/app/parser.ts", "export function parse() {}"),
+    ]);
+
+    assert!(graph
+        .graph
+        .node_weights()
+        .any(|(uri, _)| uri == "/app/parser.ts"));
+}
+
+#[test]
+fn loads_equivalent_paths_as_one_module() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { shared } from "./shared.ts";
+            import { other } from "./nested/other.ts";
+            export default function () {
+                shared();
+                other();
+            }
+            "#,
+        ),
+        (
+            "/app/nested/other.ts",
+            r#"
+            import { shared } from "/app/nested/./..//shared.ts";
+            export function other() {
+                shared();
+            }
+            "#,
+        ),
+        (
+            "/app/shared.ts",
+            r#"
+            export function shared() {}
+            "#,
+        ),
+    ]);
+
+    let shared_nodes = graph
+        .graph
+        .node_weights()
+        .filter(|(uri, _)| uri == "/app/shared.ts")
+        .count();
+    assert_eq!(shared_nodes, 1);
+}
+
+#[test]
+fn reports_re_export_cycles() {
+    let files = [
+        (
+            "/app/entry.ts",
+            r#"
+            import { x } from "./a.ts";
+            export default function () {
+                x();
+            }
+            "#,
+        ),
+        ("/app/a.ts", r#"export { x } from "./b.ts";"#),
+        ("/app/b.ts", r#"export { x } from "./a.ts";"#),
+    ];
+
+    let error = SourceGraph::load(load_params(&files)).err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Re-export cycle: x in /app/entry.ts -> x in /app/a.ts -> x in /app/b.ts -> x in /app/a.ts"
+    );
+
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { isEven } from "./even.ts";
+            export default function () {
+                isEven(2);
+            }
+            "#,
+        ),
+        (
+            "/app/even.ts",
+            r#"
+            import { isOdd } from "./odd.ts";
+            export function isEven(n) {
+                return n === 0 || isOdd(n - 1);
+            }
+            "#,
+        ),
+        (
+            "/app/odd.ts",
+            r#"
+            import { isEven } from "./even.ts";
+            export function isOdd(n) {
+                return n !== 0 && isEven(n - 1);
+            }
+            "#,
+        ),
+    ]);
+    assert_eq!(graph.graph.node_count(), 3);
+}
+
+#[test]
+fn default_and_named_exports_of_one_function_share_a_node() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import getAnswer, { getAnswer as alsoGetAnswer } from "./answer.ts";
+            export default function () {
+                return getAnswer() + alsoGetAnswer();
+            }
+            "#,
+        ),
+        (
+            "/app/answer.ts",
+            r#"
+            function getAnswer() {
+                return 42;
+            }
+            export default getAnswer;
+            export { getAnswer };
+            "#,
+        ),
+    ]);
+
+    assert_eq!(graph.graph.node_count(), 3);
+    let code = graph.to_js_execution_code(&EmitOptions {
+        source_map: SourceMapMode::None,
+        ..Default::default()
+    });
+    assert_eq!(code.matches("return 42").count(), 1);
+}
+
+#[test]
+fn awaits_async_host_functions() {
+    let double = FuneeIdentifier {
+        name: "double".to_string(),
+        uri: "funee".to_string(),
+    };
+    let graph = SourceGraph::load(LoadParams {
+        host_functions: HashSet::from([double.clone()]),
+        async_host_functions: HashSet::from([double]),
+        ..load_params(&[(
+            "/app/entry.ts",
+            r#"
+            import { double } from "funee";
+            export default async function () {
+                if (await double(21) !== 42) {
+                    throw new Error("double was not awaited");
+                }
+            }
+            "#,
+        )])
+    })
+    .unwrap();
+    let code = graph.to_js_execution_code(&Default::default());
+    assert!(code.contains("await Deno.core.opAsync("));
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(
+        &code,
+        vec![Extension::builder().ops(vec![op_double::decl()]).build()],
+        &[],
+    ));
+
+    assert!(result.is_ok());
+}
+
+#[derive(Default)]
+struct RecordingHost(Recorded);
+
+impl Host for RecordingHost {
+    fn get_extensions(&self) -> Vec<RawExtension> {
+        let recorded = self.0.clone();
+        vec![
+            RawExtension {
+                ops: vec![op_record::decl()],
+                state: Box::new(move |state: &mut OpState| {
+                    state.put(recorded.clone());
+                    Ok(())
+                }),
+            },
+            RawExtension {
+                ops: vec![op_double::decl()],
+                state: Box::new(|_| Ok(())),
+            },
+        ]
+    }
+}
+
+#[test]
+fn registers_every_op_of_the_host() {
+    let recorded = Recorded::default();
+    let request = ExecutionRequest {
+        expression: call_default_export(),
+        scope: "/app/entry.ts".to_string(),
+        host: Some(Box::new(RecordingHost(recorded.clone()))),
+        file_loader: Box::new(MockFileLoader {
+            files: HashMap::from([(
+                "/app/entry.ts".to_string(),
+                r#"
+                import { record, double } from "funee";
+                export default async function () {
+                    record("doubled " + (await double(21)));
+                }
+                "#
+                .to_string(),
+            )]),
+        }),
+        ..Default::default()
+    };
+
+    request.execute().unwrap();
+
+    assert_eq!(*recorded.0.lock().unwrap(), vec!["doubled 42".to_string()]);
+}
+
+#[test]
+fn traces_the_hops_of_each_resolution() {
+    let graph = SourceGraph::load(LoadParams {
+        trace_resolution: true,
+        ..load_params(&[
+            (
+                "/app/entry.ts",
+                r#"
+                import { x } from "./index.ts";
+                export default function () {
+                    x();
+                }
+                "#,
+            ),
+            ("/app/index.ts", r#"export { x } from "./impl.ts";"#),
+            (
+                "/app/impl.ts",
+                r#"
+                import { log } from "funee";
+                export function x() {
+                    log("x");
+                }
+                "#,
+            ),
+        ])
+    })
+    .unwrap();
+
+    assert_eq!(
+        graph.resolution_trace,
+        vec![
+            "default in /app/entry.ts",
+            "x in /app/entry.ts -> x in /app/index.ts -> x in /app/impl.ts",
+            "log in /app/impl.ts -> log in funee",
+        ]
+    );
+}
+
+#[test]
+fn builds_a_runtime_from_a_raw_extension() {
+    let extension = RawExtension {
+        ops: vec![op_greeting::decl()],
+        state: Box::new(|state| {
+            state.put(Greeting("hello".to_string()));
+            Ok(())
+        }),
+    };
+    let mut runtime = build_runtime(vec![extension.into_extension()], None);
+
+    let result = runtime.execute_script(
+        "[funee:test.js]",
+        r#"if (Deno.core.opSync("op_greeting") !== "hello") { throw new Error("no greeting"); }"#,
+    );
+
+    assert!(result.is_ok());
+}
+
+#[test]
+fn keeps_async_and_generator_flags_of_default_functions() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        export default async function* () {
+            yield 1;
+        }
+        "#,
+    )]);
+
+    let code = graph.to_js_execution_code(&EmitOptions {
+        source_map: SourceMapMode::None,
+        ..Default::default()
+    });
+
+    assert!(code.contains("async function* declaration_"));
+}
+
+#[test]
+fn returns_the_value_of_the_expression() {
+    for source in [
+        "export default function () { return 21 * 2; }",
+        "export default async function () { return 21 * 2; }",
+    ] {
+        let request = ExecutionRequest {
+            expression: call_default_export(),
+            scope: "/app/entry.ts".to_string(),
+            file_loader: Box::new(MockFileLoader {
+                files: HashMap::from([("/app/entry.ts".to_string(), source.to_string())]),
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(request.execute().unwrap().as_f64(), Some(42.0));
+    }
+}
+
+#[test]
+fn completes_with_null_for_values_without_json() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    for js in [
+        "Symbol('completion')",
+        "(() => { const cyclic = {}; cyclic.self = cyclic; return cyclic; })()",
+        "10n",
+    ] {
+        let result = runtime.block_on(run_js(js, vec![], &[])).unwrap();
+
+        assert_eq!(result, serde_json::Value::Null);
+    }
+}
+
+#[test]
+fn names_namespace_properties_that_are_not_identifiers() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import * as lib from "./lib.ts";
+            export default function () {
+                return lib["kebab-case"]() + Object.keys(lib).includes("__proto__");
+            }
+            "#,
+        ),
+        (
+            "/app/lib.ts",
+            r#"
+            function kebab() {
+                return "kebab";
+            }
+            const proto = {};
+            export { kebab as "kebab-case", proto as "__proto__" };
+            "#,
+        ),
+    ]);
+    let code = graph.to_js_execution_code(&Default::default());
+    assert_parses(&code);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+
+    assert_eq!(result, serde_json::json!("kebabtrue"));
+}
+
+#[test]
+fn resolves_namespace_re_exports_through_star_exports() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { ns } from "./index.ts";
+            export default function () {
+                return ns.fromA() + ns.fromB();
+            }
+            "#,
+        ),
+        ("/app/index.ts", r#"export * as ns from "./a.ts";"#),
+        (
+            "/app/a.ts",
+            r#"
+            export * from "./b.ts";
+            export function fromA() {
+                return "a";
+            }
+            "#,
+        ),
+        (
+            "/app/b.ts",
+            r#"
+            export function fromB() {
+                return "b";
+            }
+            "#,
+        ),
+    ]);
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+
+    assert_eq!(result, serde_json::json!("ab"));
+}
+
+#[test]
+fn starts_strict_output_with_the_directive() {
+    let literal = "a rather long string literal that repeats";
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        export default function () {
+            return ["a rather long string literal that repeats", "a rather long string literal that repeats"];
+        }
+        "#,
+    )]);
+
+    let code = graph.to_js_execution_code(&EmitOptions {
+        strict: true,
+        hoist_string_literals: Some(Default::default()),
+        source_map: SourceMapMode::None,
+        ..Default::default()
+    });
+
+    assert!(code.starts_with("\"use strict\";"));
+    assert_eq!(code.matches(literal).count(), 1);
+}
+
+struct AsyncHost;
+
+impl Host for AsyncHost {
+    fn get_extensions(&self) -> Vec<RawExtension> {
+        vec![RawExtension {
+            ops: vec![op_double::decl(), op_mark_done::decl()],
+            state: Box::new(|_| Ok(())),
+        }]
+    }
+}
+
+#[test]
+fn settles_a_promise_returned_by_the_expression_before_returning() {
+    let cm = SourceMap::default();
+    let fm = cm.new_source_file(
+        FileName::Anon,
+        "(async () => { mark_done(await double(21)); })()".to_string(),
+    );
+    let expression = parse_file_as_expr(
+        &fm,
+        Syntax::Es(Default::default()),
+        ast::EsVersion::latest(),
+        None,
+        &mut vec![],
+    )
+    .unwrap();
+    let request = ExecutionRequest {
+        expression: *expression,
+        scope: "/app/entry.ts".to_string(),
+        host: Some(Box::new(AsyncHost)),
+        file_loader: Box::new(MockFileLoader {
+            files: HashMap::from([(
+                "/app/entry.ts".to_string(),
+                r#"import { double, mark_done } from "funee";"#.to_string(),
+            )]),
+        }),
+        ..Default::default()
+    };
+
+    request.execute().unwrap();
+
+    assert!(MARKED_DONE.load(Ordering::SeqCst));
+}
+
+#[test]
+fn maps_thrown_errors_back_to_the_original_modules() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { fail } from "./another.ts";
+            export default function () {
+                fail();
+            }
+            "#,
+        ),
+        (
+            "/app/another.ts",
+            r#"
+            export function fail() {
+                throw new Error("failed");
+            }
+            "#,
+        ),
+    ]);
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let error = runtime.block_on(run_js(&code, vec![], &[])).err().unwrap();
+
+    assert!(error.to_string().contains("/app/another.ts"));
+}
+
+#[test]
+fn rejects_imports_outside_the_allowed_extensions() {
+    let code_only = Some(HashSet::from(["ts".to_string(), "js".to_string()]));
+    let files = [
+        (
+            "/app/entry.ts",
+            r#"
+            import { secret } from "./.env";
+            export default function () {
+                return secret;
+            }
+            "#,
+        ),
+        ("/app/.env", "SECRET=1"),
+    ];
+
+    let error = SourceGraph::load(LoadParams {
+        allowed_extensions: code_only.clone(),
+        ..load_params(&files)
+    })
+    .err()
+    .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Importing \".env\" files is not allowed, cannot import /app/.env"
+    );
+
+    let graph = SourceGraph::load(LoadParams {
+        allowed_extensions: code_only,
+        ..load_params(&[
+            (
+                "/app/entry.ts",
+                r#"
+                import { answer } from "./answer.ts";
+                export default function () {
+                    return answer;
+                }
+                "#,
+            ),
+            ("/app/answer.ts", "export const answer = 42;"),
+        ])
+    });
+    assert!(graph.is_ok());
+}
+
+#[test]
+fn rejects_star_re_exports_outside_the_allowed_extensions() {
+    let ts_only = Some(HashSet::from(["ts".to_string()]));
+    for entry in [
+        r#"
+        import { secret } from "./index.ts";
+        export default function () {
+            return secret;
+        }
+        "#,
+        r#"
+        import * as lib from "./index.ts";
+        export default function () {
+            return lib;
+        }
+        "#,
+    ] {
+        let error = SourceGraph::load(LoadParams {
+            allowed_extensions: ts_only.clone(),
+            ..load_params(&[
+                ("/app/entry.ts", entry),
+                ("/app/index.ts", r#"export * from "./secret.js";"#),
+                ("/app/secret.js", "export const secret = 1;"),
+            ])
+        })
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "Importing \".js\" files is not allowed, cannot import /app/secret.js"
+        );
+    }
+}
+
+#[test]
+fn annotates_declarations_with_their_origin() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { greet } from "./another.ts";
+            export default function () {
+                greet();
+            }
+            "#,
+        ),
+        (
+            "/app/another.ts",
+            r#"
+            import { log } from "funee";
+            export function greet() {
+                log(greeting);
+            }
+            const greeting = "hello";
+            "#,
+        ),
+    ]);
+
+    let code = graph.to_js_execution_code(&EmitOptions {
+        annotate_declarations: true,
+        source_map: SourceMapMode::None,
+        ..Default::default()
+    });
+
+    assert!(code.contains("// sourceURL: /app/entry.ts#default"));
+    assert!(code.contains("// sourceURL: /app/another.ts#greet"));
+    assert!(code.contains("// sourceURL: /app/another.ts#greeting"));
+}
+
+#[test]
+fn follows_superclasses_of_class_expressions() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { ImportedBase } from "./base.ts";
+            const C = class Named extends ImportedBase {
+                clone() {
+                    return new Named();
+                }
+            };
+            export default function () {
+                return new C();
+            }
+            "#,
+        ),
+        ("/app/base.ts", "export class ImportedBase {}"),
+    ]);
+
+    let uris: Vec<&str> = graph
+        .graph
+        .node_weights()
+        .map(|(uri, _)| uri.as_str())
+        .collect();
+    assert!(uris.contains(&"/app/base.ts"));
+    assert_eq!(graph.graph.node_count(), 4);
+}
+
+#[test]
+fn lowers_host_functions_to_a_configured_call() {
+    let graph = load_graph(&[(
+        "/app/entry.ts",
+        r#"
+        import { log } from "funee";
+        export default function () {
+            log("hello", "world");
+        }
+        "#,
+    )]);
+
+    let code = graph.to_js_execution_code(&EmitOptions {
+        host_call: HostCall::Global {
+            callee: "globalThis.__host.call".to_string(),
+        },
+        source_map: SourceMapMode::None,
+        ..Default::default()
+    });
+
+    assert!(code.contains("return globalThis.__host.call("));
+    assert!(code.contains(", ...args);"));
+    assert!(!code.contains("Deno.core"));
+}
+
+#[test]
+fn ignores_fragments_of_remote_modules() {
+    let files = [
+        (
+            "/app/entry.ts",
+            r#"
+            import { greet } from "https://example.com/greet.ts#first";
+            import { greet as greetAgain } from "https://example.com/greet.ts#second";
+            export default function () {
+                greet();
+                greetAgain();
+            }
+            "#,
+        ),
+        (
+            "https://example.com/greet.ts",
+            r#"
+            import { log } from "funee";
+            export function greet() {
+                log("hello");
+            }
+            "#,
+        ),
+    ];
+    let reads = Arc::new(Mutex::new(HashMap::new()));
+
+    let graph = SourceGraph::load(LoadParams {
+        file_loader: Box::new(CountingFileLoader {
+            inner: MockFileLoader {
+                files: files
+                    .iter()
+                    .map(|(path, source)| (path.to_string(), source.to_string()))
+                    .collect(),
+            },
+            reads: reads.clone(),
+        }),
+        ..load_params(&files)
+    })
+    .unwrap();
+
+    let remote_nodes = graph
+        .graph
+        .node_weights()
+        .filter(|(uri, _)| uri == "https://example.com/greet.ts")
+        .count();
+    assert_eq!(remote_nodes, 1);
+    assert_eq!(reads.lock().unwrap()["https://example.com/greet.ts"], 1);
+}
+
+fn tar_archive(files: &[(&str, &str)]) -> Vec<u8> {
+    tar_archive_of_entries(
+        &files
+            .iter()
+            .map(|(name, contents)| (*name, b'0', *contents))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn tar_archive_of_entries(entries: &[(&str, u8, &str)]) -> Vec<u8> {
+    let mut archive = vec![];
+    for (name, typeflag, contents) in entries {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+        header[148..156].copy_from_slice(b"        ");
+        header[156] = *typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(contents.as_bytes());
+        archive.resize(archive.len().div_ceil(512) * 512, 0);
+    }
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+#[test]
+fn bundles_modules_from_a_tar_archive() {
+    let archive = tar_archive(&[
+        (
+            "src/main.ts",
+            r#"
+            import { origin } from "./origin.ts";
+            export default function () {
+                return origin();
+            }
+            "#,
+        ),
+        (
+            "src/origin.ts",
+            r#"
+            export function origin() {
+                return "archive";
+            }
+            "#,
+        ),
+    ]);
+
+    let graph = SourceGraph::load(LoadParams {
+        scope: "/src/main.ts".to_string(),
+        file_loader: Box::new(TarFileLoader::new(&archive).unwrap()),
+        ..load_params(&[("/unused.ts", "")])
+    })
+    .unwrap();
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+
+    assert_eq!(result, serde_json::json!("archive"));
+}
+
+#[test]
+fn loads_tar_archives_with_directories_and_rejects_other_entry_types() {
+    let archive = tar_archive_of_entries(&[
+        ("src/", b'5', ""),
+        ("src/main.ts", b'0', "export default 1;"),
+    ]);
+    assert!(TarFileLoader::new(&archive)
+        .unwrap()
+        .file_exists(std::path::Path::new("/src/main.ts")));
+
+    for typeflag in [b'2', b'L', b'x'] {
+        let archive = tar_archive_of_entries(&[("src/main.ts", typeflag, "")]);
+        let error = TarFileLoader::new(&archive).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Unsupported entry type '{}' of src/main.ts in tar archive",
+                typeflag as char
+            )
+        );
+    }
+}
+
+#[test]
+fn emits_default_arrow_functions_under_internal_names() {
+    let graph = load_graph(&[("/app/entry.ts", "export default () => 21 * 2;")]);
+    let code = graph.to_js_execution_code(&EmitOptions {
+        source_map: SourceMapMode::None,
+        ..Default::default()
+    });
+
+    assert_parses(&code);
+    assert!(!code.contains("default"));
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+    assert_eq!(result.as_f64(), Some(42.0));
+}
+
+#[test]
+fn imports_json_modules_as_their_value() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import config from "./config.json";
+            export default function () {
+                return config.server.ports[1] + config["display name"].length;
+            }
+            "#,
+        ),
+        (
+            "/app/config.json",
+            r#"{ "server": { "ports": [8080, 8081], "tls": null }, "display name": "funee" }"#,
+        ),
+    ]);
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+
+    assert_eq!(result.as_f64(), Some(8086.0));
+}
+
+#[test]
+fn keeps_json_proto_keys_as_own_properties() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import data from "./data.json";
+            export default function () {
+                return Object.keys(data).includes("__proto__") && data.__proto__.polluted;
+            }
+            "#,
+        ),
+        ("/app/data.json", r#"{ "__proto__": { "polluted": true } }"#),
+    ]);
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+
+    assert_eq!(result, serde_json::json!(true));
+}
+
+#[test]
+fn builds_a_request_calling_an_export_with_arguments() {
+    let request = ExecutionRequest::builder()
+        .entry_file("/app/entry.ts")
+        .export("sum")
+        .args(["20", "{ extra: 22 }"])
+        .file_loader(Box::new(MockFileLoader {
+            files: HashMap::from([(
+                "/app/entry.ts".to_string(),
+                r#"
+                export function sum(a: number, { extra }: { extra: number }) {
+                    return a + extra;
+                }
+                "#
+                .to_string(),
+            )]),
+        }))
+        .build()
+        .unwrap();
+
+    assert_eq!(request.execute().unwrap(), serde_json::json!(42));
+}
+
+#[test]
+fn builds_a_request_with_options() {
+    let request = ExecutionRequest::builder()
+        .entry_file("/app/entry.ts")
+        .globals(HashMap::from([(
+            "config".to_string(),
+            serde_json::json!({ "answer": 42 }),
+        )]))
+        .allowed_extensions(["ts"])
+        .prune_unused_ops(true)
+        .file_loader(Box::new(MockFileLoader {
+            files: HashMap::from([(
+                "/app/entry.ts".to_string(),
+                r#"
+                export default function () {
+                    return config.answer;
+                }
+                "#
+                .to_string(),
+            )]),
+        }))
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        request.allowed_extensions,
+        Some(HashSet::from(["ts".to_string()]))
+    );
+    assert!(request.prune_unused_ops);
+    assert_eq!(request.execute().unwrap(), serde_json::json!(42));
+}
+
+#[test]
+fn resets_the_spans_of_parsed_arguments() {
+    struct Spans(Vec<Span>);
+
+    impl Visit for Spans {
+        noop_visit_type!();
+
+        fn visit_span(&mut self, span: &Span) {
+            self.0.push(*span);
+        }
+    }
+
+    let request = ExecutionRequest::builder()
+        .entry_file("/app/entry.ts")
+        .args(["{ answer: 42 }"])
+        .build()
+        .unwrap();
+    let mut spans = Spans(vec![]);
+    request.expression.visit_with(&mut spans);
+
+    assert!(!spans.0.is_empty());
+    assert!(spans.0.iter().all(|span| span.is_dummy()));
+}
+
+#[test]
+fn runs_the_entry_statements_as_a_script() {
+    let recorded = Recorded::default();
+    ExecutionRequest::builder()
+        .entry_file("/app/script.ts")
+        .entry_mode(EntryMode::Script)
+        .host(Box::new(RecordingHost(recorded.clone())))
+        .file_loader(Box::new(MockFileLoader {
+            files: HashMap::from([
+                (
+                    "/app/script.ts".to_string(),
+                    r#"
+                    import { record } from "funee";
+                    import { label } from "./label.ts";
+                    record(label("first"));
+                    function second() {
+                        record(label("second"));
+                    }
+                    second();
+                    "#
+                    .to_string(),
+                ),
+                (
+                    "/app/label.ts".to_string(),
+                    r#"
+                    export function label(step: string) {
+                        return "script " + step;
+                    }
+                    "#
+                    .to_string(),
+                ),
+            ]),
+        }))
+        .build()
+        .unwrap()
+        .execute()
+        .unwrap();
+
+    assert_eq!(
+        *recorded.0.lock().unwrap(),
+        vec!["script first", "script second"]
+    );
+}
+
+#[test]
+fn checks_the_extension_of_a_script_entry() {
+    let error = SourceGraph::load(LoadParams {
+        entry_mode: EntryMode::Script,
+        allowed_extensions: Some(HashSet::from(["js".to_string()])),
+        ..load_params(&[("/app/script.ts", "console.log('run');")])
+    })
+    .err()
+    .unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "Importing \".ts\" files is not allowed, cannot import /app/script.ts"
+    );
+}
+
+#[test]
+fn registers_a_script_entry_in_the_source_map_once() {
+    let graph = SourceGraph::load(LoadParams {
+        entry_mode: EntryMode::Script,
+        ..load_params(&[
+            (
+                "/app/script.ts",
+                r#"
+                import { greet } from "./greet.ts";
+                greet();
+                "#,
+            ),
+            (
+                "/app/greet.ts",
+                r#"
+                export function greet() {}
+                "#,
+            ),
+        ])
+    })
+    .unwrap();
+
+    let names: Vec<_> = graph
+        .source_map
+        .files()
+        .iter()
+        .map(|file| file.name.to_string())
+        .collect();
+    assert_eq!(names, vec!["/app/script.ts", "/app/greet.ts"]);
+}
+
+#[test]
+fn streams_the_same_bundle_the_string_api_returns() {
+    let files = [
+        (
+            "/app/entry.ts",
+            r#"
+            import { greet } from "./greet.ts";
+            export default function () {
+                greet();
+            }
+            "#,
+        ),
+        (
+            "/app/greet.ts",
+            r#"
+            import { record } from "funee";
+            export function greet() {
+                record("streamed");
+            }
+            "#,
+        ),
+    ];
+    let mut params = load_params(&files);
+    params.host_functions = HashSet::from([FuneeIdentifier {
+        name: "record".to_string(),
+        uri: "funee".to_string(),
+    }]);
+    let code = SourceGraph::load(params)
+        .unwrap()
+        .to_js_execution_code(&Default::default());
+
+    let mut written = vec![];
+    ExecutionRequest::builder()
+        .entry_file("/app/entry.ts")
+        .host(Box::new(RecordingHost::default()))
+        .file_loader(Box::new(MockFileLoader {
+            files: files
+                .iter()
+                .map(|(path, source)| (path.to_string(), source.to_string()))
+                .collect(),
+        }))
+        .build()
+        .unwrap()
+        .bundle_to_writer(&mut written)
+        .unwrap();
+
+    assert_eq!(String::from_utf8(written).unwrap(), code);
+}
+
+#[test]
+fn keeps_top_level_let_bindings_assignable() {
+    let graph = load_graph(&[
+        (
+            "/app/entry.ts",
+            r#"
+            import { increment } from "./counter.ts";
+            export default function () {
+                increment();
+                return increment();
+            }
+            "#,
+        ),
+        (
+            "/app/counter.ts",
+            r#"
+            let count = 0;
+            export function increment() {
+                count++;
+                return count;
+            }
+            "#,
+        ),
+    ]);
+    let code = graph.to_js_execution_code(&Default::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(run_js(&code, vec![], &[])).unwrap();
+
+    assert_eq!(result, serde_json::json!(2));
+}
+
+#[test]
+fn strips_a_single_op_prefix_from_host_function_names() {
+    assert_eq!(op_identifier(&op_op_codes::decl()).name, "op_codes");
+    assert_eq!(op_identifier(&op_log::decl()).name, "log");
 }
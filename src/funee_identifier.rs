@@ -1,4 +1,6 @@
-#[derive(Hash, PartialEq, Eq, Clone, Debug, PartialOrd, Ord)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Hash, PartialEq, Eq, Clone, Debug, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct FuneeIdentifier {
     pub uri: String,
     pub name: String,
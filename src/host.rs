@@ -0,0 +1,33 @@
+use crate::funee_identifier::FuneeIdentifier;
+use deno_core::{error::AnyError, Extension, OpDecl, OpState};
+
+/// The module specifier bundled code imports host functions from.
+pub const HOST_URI: &str = "funee";
+
+/// A group of ops a host registers with the runtime, along with the
+/// `OpState` they expect. Ops can be decorated by mapping over `ops` before
+/// handing the extension to the runtime.
+pub struct RawExtension {
+    pub ops: Vec<OpDecl>,
+    pub state: Box<dyn Fn(&mut OpState) -> Result<(), AnyError>>,
+}
+
+impl RawExtension {
+    pub fn into_extension(self) -> Extension {
+        Extension::builder().ops(self.ops).state(self.state).build()
+    }
+}
+
+/// Provides the ops bundled code can call. Each op `op_<name>` is importable
+/// as `<name>` from `"funee"`.
+pub trait Host {
+    fn get_extensions(&self) -> Vec<RawExtension>;
+}
+
+/// The identifier bundled code imports `op` by.
+pub fn op_identifier(op: &OpDecl) -> FuneeIdentifier {
+    FuneeIdentifier {
+        name: op.name.strip_prefix("op_").unwrap_or(op.name).to_string(),
+        uri: HOST_URI.to_string(),
+    }
+}
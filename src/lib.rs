@@ -1,5 +1,7 @@
 mod emit_module;
 pub mod execution_request;
 mod funee_identifier;
+pub mod host;
 mod load_module;
+pub mod ops;
 mod run_js;
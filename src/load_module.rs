@@ -1,23 +1,211 @@
-use std::rc::Rc;
-use swc_common::{Globals, Mark, SourceMap, GLOBALS};
-use swc_ecma_ast::EsVersion;
+use deno_core::error::{generic_error, AnyError};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+use swc_common::{
+    comments::SingleThreadedComments, source_map::RealFileLoader, FileLoader, FileName,
+    FilePathMapping, Globals, Mark, SourceMap, SyntaxContext, GLOBALS,
+};
+use swc_ecma_ast::{EsVersion, Ident};
 use swc_ecma_parser::{parse_file_as_module, Syntax::Typescript, TsConfig};
+use swc_ecma_transforms_react::{jsx, Options, Runtime};
 use swc_ecma_transforms_typescript::strip;
-use swc_ecma_visit::FoldWith;
-
-pub fn load_module(cm: &Rc<SourceMap>, path: std::path::PathBuf) -> swc_ecma_ast::Module {
-    let m = parse_file_as_module(
-        &*cm.load_file(&path).unwrap(),
-        Typescript(TsConfig {
-            ..Default::default()
-        }),
-        EsVersion::latest(),
-        None,
-        &mut vec![],
-    )
-    .expect("failed to parse input as a module");
-
-    let globals = Globals::default();
-    let module = GLOBALS.set(&globals, || m.fold_with(&mut strip(Mark::new())));
-    module
+use swc_ecma_visit::{noop_visit_mut_type, FoldWith, VisitMut, VisitMutWith};
+
+/// Compiles the raw contents of a file (given its path and source) into
+/// TypeScript or JavaScript before it is parsed.
+pub type SourceTransform = Box<dyn Fn(&str, &str) -> Result<String, AnyError>>;
+
+pub type SourceTransforms = HashMap<String, SourceTransform>;
+
+/// Syntax the parser accepts beyond plain TypeScript.
+#[derive(Default)]
+pub struct ParseOptions {
+    /// Accepts decorators, which are kept in the output as written. No
+    /// decorators transform runs, so V8 can't evaluate a bundle using them;
+    /// this is for emitting code another tool compiles further.
+    pub decorators: bool,
+    /// Parses JSX in every module, not only in `.tsx` and `.jsx` files.
+    pub jsx: bool,
+    pub jsx_runtime: JsxRuntime,
+}
+
+/// How JSX elements are compiled into function calls.
+pub enum JsxRuntime {
+    /// Calls `pragma(type, props, ...children)`, such as
+    /// `React.createElement("div", null)`.
+    Classic { pragma: String, pragma_frag: String },
+    /// Imports the element factories from `{import_source}/jsx-runtime`, a
+    /// bare specifier that needs an import map entry to resolve.
+    Automatic { import_source: String },
+}
+
+impl Default for JsxRuntime {
+    fn default() -> Self {
+        Self::Classic {
+            pragma: "React.createElement".to_string(),
+            pragma_frag: "React.Fragment".to_string(),
+        }
+    }
+}
+
+impl JsxRuntime {
+    fn options(&self) -> Options {
+        match self {
+            Self::Classic {
+                pragma,
+                pragma_frag,
+            } => Options {
+                runtime: Some(Runtime::Classic),
+                pragma: pragma.clone(),
+                pragma_frag: pragma_frag.clone(),
+                ..Default::default()
+            },
+            Self::Automatic { import_source } => Options {
+                runtime: Some(Runtime::Automatic),
+                import_source: import_source.clone(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// The transforms mark the identifiers they create, while references are
+/// collected per declaration by a resolver that only marks unmarked ones.
+struct ClearSyntaxContexts;
+
+impl VisitMut for ClearSyntaxContexts {
+    noop_visit_mut_type!();
+
+    fn visit_mut_ident(&mut self, n: &mut Ident) {
+        n.span.ctxt = SyntaxContext::empty();
+    }
+}
+
+/// A `FileLoader` that can also read files as bytes, for assets that aren't
+/// UTF-8 text.
+pub trait BinaryFileLoader: FileLoader {
+    /// Defaults to the bytes of `read_file`, which fails for files that
+    /// aren't UTF-8.
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.read_file(path).map(String::into_bytes)
+    }
+}
+
+impl BinaryFileLoader for RealFileLoader {
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+}
+
+/// Reads modules through the file loader of its source map, registering
+/// each in the source map once, with the source that is parsed rather than
+/// the file as read.
+pub struct ModuleLoader {
+    pub cm: Rc<SourceMap>,
+    file_loader: Arc<dyn BinaryFileLoader + Sync + Send>,
+}
+
+/// Lets the source map and the `ModuleLoader` owning it read through one
+/// file loader.
+struct SharedFileLoader(Arc<dyn BinaryFileLoader + Sync + Send>);
+
+impl FileLoader for SharedFileLoader {
+    fn file_exists(&self, path: &Path) -> bool {
+        self.0.file_exists(path)
+    }
+
+    fn abs_path(&self, path: &Path) -> Option<PathBuf> {
+        self.0.abs_path(path)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        self.0.read_file(path)
+    }
+}
+
+impl ModuleLoader {
+    pub fn new(file_loader: Box<dyn BinaryFileLoader + Sync + Send>) -> Self {
+        let file_loader: Arc<dyn BinaryFileLoader + Sync + Send> = Arc::from(file_loader);
+        Self {
+            cm: Rc::new(SourceMap::with_file_loader(
+                Box::new(SharedFileLoader(file_loader.clone())),
+                FilePathMapping::empty(),
+            )),
+            file_loader,
+        }
+    }
+
+    /// Reads a file as is, without registering it in the source map.
+    pub fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.file_loader.read_bytes(path)
+    }
+
+    pub fn load_module(
+        &self,
+        path: PathBuf,
+        source_transform: Option<&SourceTransform>,
+        parse_options: &ParseOptions,
+    ) -> Result<swc_ecma_ast::Module, AnyError> {
+        let mut source = self.file_loader.read_file(&path).map_err(|error| {
+            generic_error(format!("Could not load {}: {}", path.display(), error))
+        })?;
+        if let Some(source_transform) = source_transform {
+            source = source_transform(&path.to_string_lossy(), &source)?;
+        }
+        // A byte order mark would keep a shebang from being recognized, as the
+        // parser only accepts one at the very start of the file.
+        if let Some(stripped) = source.strip_prefix('\u{feff}') {
+            source = stripped.to_string();
+        }
+        let source_file = self
+            .cm
+            .new_source_file(FileName::Real(path.clone()), source);
+        let tsx = parse_options.jsx
+            || matches!(
+                path.extension().and_then(|extension| extension.to_str()),
+                Some("tsx" | "jsx")
+            );
+        let m = parse_file_as_module(
+            &*source_file,
+            Typescript(TsConfig {
+                tsx,
+                decorators: parse_options.decorators,
+                ..Default::default()
+            }),
+            EsVersion::latest(),
+            None,
+            &mut vec![],
+        )
+        .map_err(|error| {
+            let position = self.cm.lookup_char_pos(error.span().lo);
+            generic_error(format!(
+                "Could not parse {}:{}:{}: {}",
+                path.display(),
+                position.line,
+                position.col.0 + 1,
+                error.kind().msg()
+            ))
+        })?;
+
+        let globals = Globals::default();
+        let mut module = GLOBALS.set(&globals, || {
+            let module = m.fold_with(&mut strip(Mark::new()));
+            if !tsx {
+                return module;
+            }
+            module.fold_with(&mut jsx(
+                self.cm.clone(),
+                None::<SingleThreadedComments>,
+                parse_options.jsx_runtime.options(),
+                Mark::new(),
+            ))
+        });
+        module.visit_mut_with(&mut ClearSyntaxContexts);
+        Ok(module)
+    }
 }
@@ -1,11 +1,32 @@
 mod emit_module;
 pub mod execution_request;
 mod funee_identifier;
+pub mod host;
 mod load_module;
+pub mod ops;
 mod run_js;
 
-use deno_core::error::AnyError;
+use deno_core::error::{generic_error, AnyError};
+use execution_request::ExecutionRequest;
+use ops::stdout::StdoutHost;
+
+const USAGE: &str = "usage: funee <file> [export]";
 
 fn main() -> Result<(), AnyError> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().ok_or_else(|| generic_error(USAGE))?;
+    let export = args.next().unwrap_or_else(|| "default".to_string());
+    if args.next().is_some() {
+        return Err(generic_error(USAGE));
+    }
+
+    let path = std::fs::canonicalize(&path)
+        .map_err(|error| generic_error(format!("Could not find {}: {}", path, error)))?;
+    ExecutionRequest::builder()
+        .entry_file(path.to_str().unwrap())
+        .export(export)
+        .host(Box::new(StdoutHost))
+        .build()?
+        .execute()?;
     Ok(())
 }
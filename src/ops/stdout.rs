@@ -0,0 +1,20 @@
+use crate::host::{Host, RawExtension};
+use deno_core::{error::AnyError, op};
+
+#[op]
+fn op_log(message: String) -> Result<(), AnyError> {
+    println!("{}", message);
+    Ok(())
+}
+
+/// Lets bundled code print lines to stdout with `log` from `"funee"`.
+pub struct StdoutHost;
+
+impl Host for StdoutHost {
+    fn get_extensions(&self) -> Vec<RawExtension> {
+        vec![RawExtension {
+            ops: vec![op_log::decl()],
+            state: Box::new(|_| Ok(())),
+        }]
+    }
+}
@@ -1,13 +1,80 @@
-use deno_core::{error::AnyError, Extension, OpDecl};
+use deno_core::{
+    error::AnyError, serde_json, v8, Extension, JsRuntime, RuntimeOptions, SourceMapGetter,
+};
+use std::collections::HashMap;
 
-pub async fn run_js(js: &str, ops: Vec<OpDecl>) -> Result<(), AnyError> {
-    let mut js_runtime = deno_core::JsRuntime::new(deno_core::RuntimeOptions {
-        extensions: vec![Extension::builder().ops(ops).build()],
+pub const SCRIPT_NAME: &str = "[funee:runtime.js]";
+
+const INLINE_SOURCE_MAP_PREFIX: &str = "//# sourceMappingURL=data:application/json;base64,";
+
+/// Reads the source map the script carries inline, so that errors thrown by
+/// it point at the original modules.
+struct InlineSourceMapGetter {
+    script: String,
+}
+
+impl SourceMapGetter for InlineSourceMapGetter {
+    fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        if file_name != SCRIPT_NAME {
+            return None;
+        }
+        let (_, encoded) = self.script.rsplit_once(INLINE_SOURCE_MAP_PREFIX)?;
+        base64::decode(encoded.trim_end()).ok()
+    }
+
+    fn get_source_line(&self, _file_name: &str, _line_number: usize) -> Option<String> {
+        None
+    }
+}
+
+pub fn build_runtime(
+    extensions: Vec<Extension>,
+    source_map_getter: Option<Box<dyn SourceMapGetter>>,
+) -> JsRuntime {
+    JsRuntime::new(RuntimeOptions {
+        extensions,
+        source_map_getter,
         ..Default::default()
-    });
+    })
+}
+
+/// Runs `js` after evaluating each of `shims`, in order, as a separate script.
+/// Returns the completion value of `js`, awaited if it is a promise.
+pub async fn run_js(
+    js: &str,
+    extensions: Vec<Extension>,
+    shims: &[String],
+) -> Result<serde_json::Value, AnyError> {
+    let mut js_runtime = build_runtime(
+        extensions,
+        Some(Box::new(InlineSourceMapGetter {
+            script: js.to_string(),
+        })),
+    );
 
-    js_runtime.execute_script("[funee:runtime.js]", js)?;
+    for (index, shim) in shims.iter().enumerate() {
+        js_runtime.execute_script(&format!("[funee:shim_{}.js]", index), shim)?;
+    }
+    let value = js_runtime.execute_script(SCRIPT_NAME, js)?;
+    let value = js_runtime.resolve_value(value).await?;
     js_runtime.run_event_loop(false).await?;
 
-    Ok(())
+    let scope = &mut js_runtime.handle_scope();
+    let value = v8::Local::new(scope, value);
+    // The value goes through JSON.stringify, which gives no JSON for symbols
+    // and functions and throws for cyclic values and BigInts. Those complete
+    // as null.
+    let scope = &mut v8::TryCatch::new(scope);
+    Ok(v8::json::stringify(scope, value)
+        .map(|json| json.to_rust_string_lossy(scope))
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or(serde_json::Value::Null))
+}
+
+/// A shim script defining each of `globals` on `globalThis`.
+pub fn globals_shim(globals: &HashMap<String, serde_json::Value>) -> String {
+    globals
+        .iter()
+        .map(|(name, value)| format!("globalThis[{}] = {};\n", serde_json::json!(name), value))
+        .collect()
 }
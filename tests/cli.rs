@@ -0,0 +1,35 @@
+use std::process::Command;
+
+fn run(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_funee"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn runs_the_default_export_of_a_file() {
+    let output = run(&["tests/fixtures/hello.ts"]);
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "hello from funee\n"
+    );
+}
+
+#[test]
+fn runs_a_named_export() {
+    let output = run(&["tests/fixtures/hello.ts", "greet"]);
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi from greet\n");
+}
+
+#[test]
+fn fails_without_a_file() {
+    let output = run(&[]);
+
+    assert!(!output.status.success());
+}
@@ -0,0 +1,9 @@
+import { log } from "funee";
+
+export function greet() {
+  log("hi from greet");
+}
+
+export default function () {
+  log("hello from funee");
+}